@@ -0,0 +1,81 @@
+use std::{hint::black_box, path::PathBuf};
+
+use aufseher::{
+    config::{Config, RegexLimitsConfig, RuleConfig},
+    matching::{self, RuleSet},
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+
+const MESSAGES: [&str; 3] = [
+    "Good morning everyone, has anyone tried the new release yet?",
+    "💰💰 Ｅａｒｎ $500 dаily with ⅽrурtо, DM me now 💰💰",
+    "Привет всем! Кто-нибудь знает, как настроить бота?",
+];
+
+fn example_config() -> Config {
+    let mut config_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    config_file.push("configs/aufseher.yaml");
+    Config::new(String::new(), None, config_file).unwrap()
+}
+
+/// Rules of the example config, which the regex set matches in a single pass
+fn bench_regex_set(c: &mut Criterion) {
+    let config = example_config();
+    c.bench_function("regex set", |b| {
+        b.iter(|| {
+            for message in MESSAGES {
+                black_box(config.message_regexes.matches(black_box(message)).unwrap());
+            }
+        })
+    });
+}
+
+/// Rules using lookaround and backreferences, which are matched one by one
+/// with the backtracking engine
+fn bench_fancy_regex(c: &mut Criterion) {
+    let patterns = [
+        r"(?i)(?<!\w)dm me(?! later)",
+        r"(\w)\1{4,}",
+        r"(?i)(?=.*crypto)(?=.*daily)",
+    ];
+    let rule_configs: Vec<RuleConfig> = patterns
+        .iter()
+        .map(|pattern| RuleConfig {
+            pattern: pattern.to_string(),
+            id: None,
+            weight: 1.0,
+            description: None,
+        })
+        .collect();
+    let rules = RuleSet::new("message", &rule_configs, &RegexLimitsConfig::default()).unwrap();
+    c.bench_function("fancy regex", |b| {
+        b.iter(|| {
+            for message in MESSAGES {
+                black_box(rules.matches(black_box(message)).unwrap());
+            }
+        })
+    });
+}
+
+/// All deobfuscation stages of the example config
+fn bench_deobfuscation(c: &mut Criterion) {
+    let config = example_config();
+    c.bench_function("deobfuscation", |b| {
+        b.iter(|| {
+            for message in MESSAGES {
+                black_box(matching::deobfuscate_message_text(
+                    black_box(message),
+                    &config.deobfuscation,
+                ));
+            }
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_regex_set,
+    bench_fancy_regex,
+    bench_deobfuscation
+);
+criterion_main!(benches);
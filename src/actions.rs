@@ -1,67 +1,469 @@
+use std::fmt;
+
 use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
 use teloxide::{
     prelude::*,
-    types::{Message, ReplyParameters, User},
-    utils::markdown::escape,
+    types::{ChatPermissions, Message, MessageId, ReplyParameters, User},
 };
 use tokio::{time, time::Duration};
-use tracing::warn;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    flood::FloodViolation,
+    metrics::METRICS,
+    notices::{self, Notice},
+    privacy,
+    retry::{self, Outcome},
+    state::State,
+};
+
+/// Action taken against a user depending on the spam score of their message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    None,
+    Delete,
+    Mute,
+    Ban,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::None => write!(f, "none"),
+            Action::Delete => write!(f, "delete"),
+            Action::Mute => write!(f, "mute"),
+            Action::Ban => write!(f, "ban"),
+        }
+    }
+}
 
-pub async fn delete_messages_and_ban_user(
+/// Takes the action against the user
+///
+/// The steps of the action are taken independently, so the outcome records
+/// whether the punishment succeeded and which steps failed. An error is only
+/// returned if the member status of the user cannot be checked.
+pub async fn enforce(
     bot: &Bot,
+    state: &State,
+    message: &Message,
+    user: &User,
+    action: Action,
+    rule: &str,
+    config: &Config,
+) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+    if action == Action::None {
+        return Ok(outcome);
+    }
+
+    let chat_title = message.chat.title().unwrap_or("None");
+    let settings = state.settings.get(message.chat.id);
+    if settings.shadow_mode {
+        log_shadow_action(message.chat.id, user.id, chat_title, &action.to_string());
+        return Ok(outcome);
+    }
+    if is_exempt(
+        bot,
+        state,
+        message.chat.id,
+        user,
+        chat_title,
+        &action.to_string(),
+    )
+    .await?
+    {
+        return Ok(outcome);
+    }
+
+    outcome.taken = match action {
+        Action::None => false,
+        Action::Delete => delete_message(bot, message, user, chat_title, &mut outcome).await,
+        Action::Mute => {
+            delete_message_and_mute_user(
+                bot,
+                message,
+                user,
+                chat_title,
+                config.mute_duration,
+                &mut outcome,
+            )
+            .await
+        }
+        Action::Ban => {
+            delete_messages_and_ban_user(bot, message, user, chat_title, &mut outcome).await
+        }
+    };
+    if !outcome.taken {
+        return Ok(outcome);
+    }
+
+    METRICS
+        .actions
+        .with_label_values(&[action.to_string()])
+        .inc();
+
+    let notice = Notice {
+        kind: &action.to_string(),
+        user,
+        rule,
+        duration: if action == Action::Mute {
+            config.mute_duration
+        }
+        else {
+            0
+        },
+    };
+    if settings.notices_enabled(message.chat.id, config)
+        && let Err(error) = notices::send_notice(bot, message.chat.id, &notice, config).await
+    {
+        outcome.fail("send notice", &error);
+    }
+    Ok(outcome)
+}
+
+/// Deletes the messages of a flood and mutes the user, depending on the flood settings
+pub async fn enforce_flood(
+    bot: &Bot,
+    state: &State,
     message: &Message,
     user: &User,
     chat_title: &str,
-) -> Result<()> {
-    // Get the member status of the user
-    let member = bot
-        .get_chat_member(message.chat.id.clone(), user.id)
-        .send()
-        .await?;
+    violation: &FloodViolation,
+    config: &Config,
+) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+    let flood = config.flood_for(message.chat.id);
+    let settings = state.settings.get(message.chat.id);
+    if settings.shadow_mode {
+        log_shadow_action(message.chat.id, user.id, chat_title, "flood action");
+        return Ok(outcome);
+    }
+    if is_exempt(
+        bot,
+        state,
+        message.chat.id,
+        user,
+        chat_title,
+        "flood action",
+    )
+    .await?
+    {
+        return Ok(outcome);
+    }
+
+    METRICS.actions.with_label_values(&["flood"]).inc();
+    if flood.delete_burst {
+        let deleted = delete_messages(bot, message.chat.id, &violation.message_ids).await;
+        if let Err(error) = &deleted {
+            outcome.fail("delete flood messages", error);
+        }
+        else {
+            outcome.taken = true;
+            warn!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                action = "flood",
+                "{} messages from user '{}' ({}) have been deleted from '{}' ({})",
+                violation.message_ids.len(),
+                privacy::redact(&user.full_name()),
+                user.id,
+                chat_title,
+                &message.chat.id
+            );
+        }
+    }
+
+    // The mute is skipped if the chat only allows deleting messages
+    if flood.mute_duration > 0 && settings.limit_action(Action::Mute) == Action::Mute {
+        let until_date = Utc::now() + TimeDelta::seconds(flood.mute_duration as i64);
+        let muted = outcome
+            .step("mute user", || {
+                bot.restrict_chat_member(message.chat.id, user.id, ChatPermissions::empty())
+                    .until_date(until_date)
+                    .send()
+            })
+            .await
+            .is_some();
+        if muted {
+            outcome.taken = true;
+            warn!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                action = "flood",
+                "User '{}' ({}) has been muted in '{}' ({}) for {} seconds for flooding",
+                privacy::redact(&user.full_name()),
+                user.id,
+                chat_title,
+                &message.chat.id,
+                flood.mute_duration
+            );
+
+            let notice = Notice {
+                kind: "flood",
+                user,
+                rule: violation.kind,
+                duration: flood.mute_duration,
+            };
+            if settings.notices_enabled(message.chat.id, config)
+                && let Err(error) =
+                    notices::send_notice(bot, message.chat.id, &notice, config).await
+            {
+                outcome.fail("send notice", &error);
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Deletes messages from a chat, skipping messages that no longer exist
+pub async fn delete_messages(bot: &Bot, chat_id: ChatId, message_ids: &[MessageId]) -> Result<()> {
+    // Telegram deletes at most 100 messages per request
+    for message_ids in message_ids.chunks(100) {
+        retry::retry(|| bot.delete_messages(chat_id, message_ids.to_vec()).send()).await?;
+    }
+
+    Ok(())
+}
+
+/// Takes the action against a user in a chat, deleting one of their messages
+/// there if given
+///
+/// Used for actions outside the chat of the message being handled, such as
+/// federated bans and bans for duplicate content. Callers limit the action to
+/// the most severe action allowed in the chat and record the reason.
+pub async fn enforce_in_chat(
+    bot: &Bot,
+    state: &State,
+    chat_id: ChatId,
+    user_id: UserId,
+    message_id: Option<MessageId>,
+    action: Action,
+    config: &Config,
+) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+    if action == Action::None {
+        return Ok(outcome);
+    }
+    if state.settings.get(chat_id).shadow_mode {
+        log_shadow_action(chat_id, user_id, &chat_id.to_string(), &action.to_string());
+        return Ok(outcome);
+    }
+
+    // Skip the action if the user is an admin or creator
+    if state.admins.is_admin(bot, chat_id, user_id).await? {
+        warn!(
+            chat_id = chat_id.0,
+            user_id = user_id.0,
+            "User {} is an admin or creator in {}. Skipping {}.",
+            user_id,
+            chat_id,
+            action
+        );
+        return Ok(outcome);
+    }
 
-    // Skip the ban if the user is an admin or creator
-    if member.is_administrator() || member.is_owner() {
+    if let Some(message_id) = message_id {
+        let deleted = outcome
+            .step("delete message", || {
+                bot.delete_message(chat_id, message_id).send()
+            })
+            .await
+            .is_some();
+        outcome.taken = deleted && action == Action::Delete;
+    }
+    match action {
+        Action::None | Action::Delete => {}
+        Action::Mute => {
+            let until_date = Utc::now() + TimeDelta::seconds(config.mute_duration as i64);
+            outcome.taken = outcome
+                .step("mute user", || {
+                    bot.restrict_chat_member(chat_id, user_id, ChatPermissions::empty())
+                        .until_date(until_date)
+                        .send()
+                })
+                .await
+                .is_some();
+        }
+        Action::Ban => {
+            outcome.taken = outcome
+                .step("ban user", || {
+                    bot.ban_chat_member(chat_id, user_id)
+                        .revoke_messages(true)
+                        .send()
+                })
+                .await
+                .is_some();
+        }
+    }
+    if outcome.taken {
+        METRICS
+            .actions
+            .with_label_values(&[action.to_string()])
+            .inc();
         warn!(
-            "User '{}' ({}) is an admin or creator in '{}'. Skipping ban.",
-            user.full_name(),
+            chat_id = chat_id.0,
+            user_id = user_id.0,
+            action = action.to_string(),
+            "Took action '{}' against user {} in {}",
+            action,
+            user_id,
+            chat_id
+        );
+    }
+
+    Ok(outcome)
+}
+
+// Chats in shadow mode only log the actions that would have been taken
+fn log_shadow_action(chat_id: ChatId, user_id: UserId, chat_title: &str, action: &str) {
+    info!(
+        chat_id = chat_id.0,
+        user_id = user_id.0,
+        action,
+        "Shadow mode is enabled in '{}' ({}). Skipping {} against user {}.",
+        chat_title,
+        chat_id,
+        action,
+        user_id
+    );
+}
+
+// Admins and creators are never punished
+async fn is_exempt(
+    bot: &Bot,
+    state: &State,
+    chat_id: ChatId,
+    user: &User,
+    chat_title: &str,
+    action: &str,
+) -> Result<bool> {
+    if state.admins.is_admin(bot, chat_id, user.id).await? {
+        warn!(
+            chat_id = chat_id.0,
+            user_id = user.id.0,
+            "User '{}' ({}) is an admin or creator in '{}'. Skipping {}.",
+            privacy::redact(&user.full_name()),
             user.id,
             chat_title,
+            action,
         );
-        return Ok(());
+        return Ok(true);
     }
+    Ok(false)
+}
 
-    bot.delete_message(message.chat.id.clone(), message.id.clone())
-        .send()
-        .await?;
-    bot.ban_chat_member(message.chat.id.clone(), user.id)
-        .revoke_messages(true)
-        .send()
-        .await?;
-    bot.send_message(
-        message.chat.id.clone(),
-        format!(
-            "User {} \\(||{}||\\) has been banned\\.",
+async fn delete_message(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    chat_title: &str,
+    outcome: &mut Outcome,
+) -> bool {
+    let deleted = outcome
+        .step("delete message", || {
+            bot.delete_message(message.chat.id, message.id).send()
+        })
+        .await
+        .is_some();
+    if deleted {
+        warn!(
+            chat_id = message.chat.id.0,
+            user_id = user.id.0,
+            action = "delete",
+            "Message from user '{}' ({}) has been deleted from '{}' ({})",
+            privacy::redact(&user.full_name()),
             user.id,
-            escape(&user.full_name())
-        ),
-    )
-    .parse_mode(teloxide::types::ParseMode::MarkdownV2)
-    .disable_notification(true)
-    .await?;
-    warn!(
-        "User '{}' ({}) has been banned from '{}' ({})",
-        user.full_name(),
-        user.id,
-        chat_title,
-        &message.chat.id
-    );
+            chat_title,
+            &message.chat.id
+        );
+    }
 
-    Ok(())
+    deleted
+}
+
+async fn delete_message_and_mute_user(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    chat_title: &str,
+    mute_duration: u64,
+    outcome: &mut Outcome,
+) -> bool {
+    outcome
+        .step("delete message", || {
+            bot.delete_message(message.chat.id, message.id).send()
+        })
+        .await;
+    let until_date = Utc::now() + TimeDelta::seconds(mute_duration as i64);
+    let muted = outcome
+        .step("mute user", || {
+            bot.restrict_chat_member(message.chat.id, user.id, ChatPermissions::empty())
+                .until_date(until_date)
+                .send()
+        })
+        .await
+        .is_some();
+    if muted {
+        warn!(
+            chat_id = message.chat.id.0,
+            user_id = user.id.0,
+            action = "mute",
+            "User '{}' ({}) has been muted in '{}' ({}) for {} seconds",
+            privacy::redact(&user.full_name()),
+            user.id,
+            chat_title,
+            &message.chat.id,
+            mute_duration
+        );
+    }
+
+    muted
+}
+
+async fn delete_messages_and_ban_user(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    chat_title: &str,
+    outcome: &mut Outcome,
+) -> bool {
+    outcome
+        .step("delete message", || {
+            bot.delete_message(message.chat.id, message.id).send()
+        })
+        .await;
+    let banned = outcome
+        .step("ban user", || {
+            bot.ban_chat_member(message.chat.id, user.id)
+                .revoke_messages(true)
+                .send()
+        })
+        .await
+        .is_some();
+    if banned {
+        warn!(
+            chat_id = message.chat.id.0,
+            user_id = user.id.0,
+            action = "ban",
+            "User '{}' ({}) has been banned from '{}' ({})",
+            privacy::redact(&user.full_name()),
+            user.id,
+            chat_title,
+            &message.chat.id
+        );
+    }
+
+    banned
 }
 
 pub async fn send_ping_response(bot: &Bot, message: &Message) -> Result<()> {
     let pong_message = bot
-        .send_message(message.chat.id.clone(), "pong!")
+        .send_message(message.chat.id, "pong!")
         .reply_parameters(ReplyParameters::new(message.id))
         .send()
         .await?;
@@ -70,10 +472,20 @@ pub async fn send_ping_response(bot: &Bot, message: &Message) -> Result<()> {
     time::sleep(Duration::from_secs(1)).await;
 
     // Delete the ping message and the pong message
-    bot.delete_message(message.chat.id.clone(), message.id.clone())
+    bot.delete_message(message.chat.id, message.id)
         .send()
         .await?;
-    bot.delete_message(message.chat.id.clone(), pong_message.id.clone())
+    bot.delete_message(message.chat.id, pong_message.id)
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+pub async fn send_command_response(bot: &Bot, message: &Message, report: &str) -> Result<()> {
+    bot.send_message(message.chat.id, report)
+        .reply_parameters(ReplyParameters::new(message.id))
+        .disable_notification(true)
         .send()
         .await?;
 
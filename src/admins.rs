@@ -0,0 +1,130 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use chrono::Utc;
+use teloxide::{prelude::*, types::ChatMember};
+use tracing::debug;
+
+use crate::retry;
+
+/// Admins and creators of a chat at the time they were fetched
+struct ChatAdmins {
+    /// Unix timestamp of the fetch
+    time: i64,
+    user_ids: HashSet<u64>,
+}
+
+/// Cache of the admins of every chat, so that the admin status of a user does
+/// not have to be requested for every message during a spam wave
+///
+/// The admins of a chat are fetched with `getChatAdministrators` when they
+/// are first needed and again after the TTL. `chat_member` updates keep the
+/// cached admins up to date in between.
+#[derive(Default)]
+pub struct AdminCache {
+    /// Seconds after which the admins of a chat are fetched again
+    ttl: u64,
+    chats: Mutex<HashMap<i64, ChatAdmins>>,
+}
+
+impl AdminCache {
+    pub fn new(ttl: u64) -> AdminCache {
+        AdminCache {
+            ttl,
+            ..AdminCache::default()
+        }
+    }
+
+    /// Returns whether the user is an admin or creator of the chat
+    pub async fn is_admin(&self, bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<bool> {
+        if let Some(is_admin) = self.cached(chat_id, user_id) {
+            return Ok(is_admin);
+        }
+
+        debug!(chat_id = chat_id.0, "Fetching the admins of {}", chat_id);
+        let admins = retry::retry(|| bot.get_chat_administrators(chat_id).send()).await?;
+        let user_ids: HashSet<u64> = admins
+            .iter()
+            .filter(|member| member.is_privileged())
+            .map(|member| member.user.id.0)
+            .collect();
+        let is_admin = user_ids.contains(&user_id.0);
+        self.store(chat_id, user_ids);
+        Ok(is_admin)
+    }
+
+    /// Updates the cached admins of a chat after the status of a member changed
+    pub fn update_member(&self, chat_id: ChatId, member: &ChatMember) {
+        let mut chats = self.chats.lock().unwrap();
+        let Some(admins) = chats.get_mut(&chat_id.0)
+        else {
+            return;
+        };
+
+        if member.is_privileged() {
+            admins.user_ids.insert(member.user.id.0);
+        }
+        else {
+            admins.user_ids.remove(&member.user.id.0);
+        }
+    }
+
+    fn cached(&self, chat_id: ChatId, user_id: UserId) -> Option<bool> {
+        let chats = self.chats.lock().unwrap();
+        let admins = chats.get(&chat_id.0)?;
+        if Utc::now().timestamp() - admins.time >= self.ttl as i64 {
+            return None;
+        }
+        Some(admins.user_ids.contains(&user_id.0))
+    }
+
+    fn store(&self, chat_id: ChatId, user_ids: HashSet<u64>) {
+        let admins = ChatAdmins {
+            time: Utc::now().timestamp(),
+            user_ids,
+        };
+        self.chats.lock().unwrap().insert(chat_id.0, admins);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use teloxide::types::{ChatId, ChatMember, UserId};
+
+    use crate::admins::AdminCache;
+
+    #[test]
+    fn test_admin_cache() {
+        let chat_id = ChatId(-100);
+        let cache = AdminCache::new(600);
+        assert_eq!(cache.cached(chat_id, UserId(1)), None);
+
+        cache.store(chat_id, HashSet::from([1]));
+        assert_eq!(cache.cached(chat_id, UserId(1)), Some(true));
+        assert_eq!(cache.cached(chat_id, UserId(2)), Some(false));
+
+        // Promotions and demotions update the cached admins
+        let member: ChatMember = serde_json::from_str(
+            r#"{"user": {"id": 2, "is_bot": false, "first_name": "Admin"}, "status": "creator", "is_anonymous": false}"#,
+        )
+        .unwrap();
+        cache.update_member(chat_id, &member);
+        assert_eq!(cache.cached(chat_id, UserId(2)), Some(true));
+        let member: ChatMember = serde_json::from_str(
+            r#"{"user": {"id": 1, "is_bot": false, "first_name": "Member"}, "status": "member"}"#,
+        )
+        .unwrap();
+        cache.update_member(chat_id, &member);
+        assert_eq!(cache.cached(chat_id, UserId(1)), Some(false));
+
+        // Expired admins are fetched again
+        let cache = AdminCache::new(0);
+        cache.store(chat_id, HashSet::from([1]));
+        assert_eq!(cache.cached(chat_id, UserId(1)), None);
+    }
+}
@@ -1,21 +1,534 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
-use fancy_regex::Regex;
+use anyhow::{Context, Result, bail};
 use serde::Deserialize;
+use teloxide::types::ChatId;
+use tracing::{info, warn};
+
+use crate::{
+    actions::Action, keywords::KeywordList, matching::RuleSet, notices, rule_store::RuleStore,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AufseherConfigFile {
-    name_regexes: Vec<String>,
-    message_regexes: Vec<String>,
+    /// Rule files to include, as paths, glob patterns or directories
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    name_regexes: Vec<RuleConfig>,
+    #[serde(default)]
+    message_regexes: Vec<RuleConfig>,
+    #[serde(default)]
+    deobfuscation: DeobfuscationConfig,
+    #[serde(default)]
+    regex_limits: RegexLimitsConfig,
+    #[serde(default)]
+    thresholds: Thresholds,
+    #[serde(default)]
+    signals: SignalsConfig,
+    /// IDs of channels and groups whose forwarded messages add the `blocked_forward` signal
+    #[serde(default)]
+    blocked_forward_chats: Vec<i64>,
+    #[serde(default = "default_mute_duration")]
+    mute_duration: u64,
+    /// Seconds after which the cached admins of a chat are fetched again
+    #[serde(default = "default_admin_cache_ttl")]
+    admin_cache_ttl: u64,
+    #[serde(default)]
+    chats: HashMap<i64, ChatConfig>,
+    #[serde(default)]
+    keywords: Vec<KeywordListConfig>,
+    #[serde(default)]
+    flood: FloodConfig,
+    #[serde(default)]
+    duplicates: DuplicatesConfig,
+    #[serde(default)]
+    federation: FederationConfig,
+    #[serde(default)]
+    history: HistoryConfig,
+    #[serde(default)]
+    notices: NoticesConfig,
+    #[serde(default)]
+    service_messages: ServiceMessagesConfig,
+    #[serde(default)]
+    reports: ReportsConfig,
+    #[serde(default)]
+    http: HttpConfig,
+    /// Chat that receives warnings for admins instead of the moderated chats
+    log_channel: Option<i64>,
+    /// Chats whose admins may manage rules with `/aufseher rule`
+    #[serde(default)]
+    rule_admin_chats: Vec<i64>,
+    /// Directory for persistent state, relative to the config file (state is
+    /// kept in memory only if unset)
+    data_dir: Option<PathBuf>,
+}
+
+fn default_mute_duration() -> u64 {
+    3600
+}
+
+fn default_admin_cache_ttl() -> u64 {
+    600
+}
+
+/// A file included by the config file, containing only rules and keyword lists
+///
+/// The IDs of all rules and keyword lists in the file are prefixed with its
+/// namespace (e.g., `community/message-3`), which defaults to the file name
+/// without extension.
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+    namespace: Option<String>,
+    #[serde(default)]
+    name_regexes: Vec<RuleConfig>,
+    #[serde(default)]
+    message_regexes: Vec<RuleConfig>,
+    #[serde(default)]
+    keywords: Vec<KeywordListConfig>,
+}
+
+/// A regex rule, either a plain pattern or a pattern with an ID, weight and description
+#[derive(Debug, Deserialize, Clone)]
+#[serde(from = "RuleConfigEntry")]
+pub struct RuleConfig {
+    pub pattern: String,
+    pub id: Option<String>,
+    pub weight: f64,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RuleConfigEntry {
+    Pattern(String),
+    Rule {
+        pattern: String,
+        id: Option<String>,
+        #[serde(default = "default_rule_weight")]
+        weight: f64,
+        description: Option<String>,
+    },
+}
+
+fn default_rule_weight() -> f64 {
+    1.0
+}
+
+/// Rejects NaN and infinite weights, which would break the score of every
+/// message they are added to
+pub fn check_weight(weight: f64, name: &str) -> Result<()> {
+    if !weight.is_finite() {
+        bail!("Invalid weight {} of {}", weight, name);
+    }
+    Ok(())
+}
+
+impl From<RuleConfigEntry> for RuleConfig {
+    fn from(entry: RuleConfigEntry) -> Self {
+        match entry {
+            RuleConfigEntry::Pattern(pattern) => RuleConfig {
+                pattern,
+                id: None,
+                weight: default_rule_weight(),
+                description: None,
+            },
+            RuleConfigEntry::Rule {
+                pattern,
+                id,
+                weight,
+                description,
+            } => RuleConfig {
+                pattern,
+                id,
+                weight,
+                description,
+            },
+        }
+    }
+}
+
+/// A list of literal keywords, given inline and/or in plain-text files with one keyword per line
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeywordListConfig {
+    pub id: Option<String>,
+    #[serde(default = "default_rule_weight")]
+    pub weight: f64,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Paths of keyword files, relative to the directory of the config file
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// Also match the message text deobfuscated word by word
+    #[serde(default)]
+    pub deobfuscate: bool,
+    /// Only match whole words
+    #[serde(default = "default_word_boundary")]
+    pub word_boundary: bool,
+}
+
+fn default_word_boundary() -> bool {
+    true
+}
+
+/// Minimum scores at which each action is taken (the most severe action reached wins)
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct Thresholds {
+    pub delete: Option<f64>,
+    pub mute: Option<f64>,
+    pub ban: Option<f64>,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            delete: None,
+            mute: None,
+            ban: Some(1.0),
+        }
+    }
+}
+
+impl Thresholds {
+    pub fn action_for(&self, score: f64) -> Action {
+        let reached =
+            |threshold: Option<f64>| threshold.is_some_and(|threshold| score >= threshold);
+
+        if reached(self.ban) {
+            Action::Ban
+        }
+        else if reached(self.mute) {
+            Action::Mute
+        }
+        else if reached(self.delete) {
+            Action::Delete
+        }
+        else {
+            Action::None
+        }
+    }
+}
+
+/// Weights of signals that are not produced by rules
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct SignalsConfig {
+    /// Added when the LLM classifies the message as spam
+    pub llm: f64,
+    /// Added when the user has no username
+    pub no_username: f64,
+    /// Added when the user has Telegram Premium (usually negative)
+    pub premium: f64,
+    /// Added when the user ID is at least `new_account_min_id`
+    pub new_account: f64,
+    /// User IDs are assigned incrementally, so high IDs belong to new accounts
+    pub new_account_min_id: u64,
+    /// Added when the message was forwarded from a blocked chat
+    pub blocked_forward: f64,
+    /// Factor applied to the weight of message rules that only match with the
+    /// quote or external reply of a message, which the sender did not write
+    pub quoted_content: f64,
+}
+
+impl Default for SignalsConfig {
+    fn default() -> Self {
+        SignalsConfig {
+            llm: 1.0,
+            no_username: 0.0,
+            premium: 0.0,
+            new_account: 0.0,
+            new_account_min_id: 7_000_000_000,
+            blocked_forward: 1.0,
+            quoted_content: 0.0,
+        }
+    }
+}
+
+/// Limits on how much a user may post in a chat within a sliding time window
+///
+/// Limits that are not set are not enforced, so flood detection is disabled by default.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct FloodConfig {
+    /// Length of the window in seconds
+    pub window: u64,
+    /// Maximum number of messages of any kind
+    pub messages: Option<usize>,
+    /// Maximum number of messages with media (photos, videos, documents, stickers, etc.)
+    pub media: Option<usize>,
+    /// Maximum number of messages containing links
+    pub links: Option<usize>,
+    /// Maximum number of forwarded messages
+    pub forwards: Option<usize>,
+    /// Mute users exceeding a limit for this many seconds (0 to not mute)
+    pub mute_duration: u64,
+    /// Delete all messages the user sent within the window
+    pub delete_burst: bool,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        FloodConfig {
+            window: 10,
+            messages: None,
+            media: None,
+            links: None,
+            forwards: None,
+            mute_duration: 600,
+            delete_burst: true,
+        }
+    }
+}
+
+/// Detection of the same content posted by different users or in different chats
+///
+/// Limits that are not set are not enforced, so duplicate detection is disabled by default.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DuplicatesConfig {
+    /// Length of the window in seconds
+    pub window: u64,
+    /// Messages shorter than this many characters are not fingerprinted
+    pub min_length: usize,
+    /// Maximum number of differing simhash bits for near-duplicates
+    pub max_distance: u32,
+    /// Number of distinct users posting the same content at which it is spam
+    pub users: Option<usize>,
+    /// Number of distinct chats the same content is posted in at which it is spam
+    pub chats: Option<usize>,
+    /// Weight added to the score of messages with duplicate content if the
+    /// other signals reach the mute threshold on their own (repeated content
+    /// alone is no proof of spam)
+    pub weight: f64,
+    /// Keep treating the content as spam for this many seconds
+    pub block_duration: u64,
+    /// Ban the users who posted the content earlier in every chat they posted
+    /// it in, if the message reaches the mute threshold even without the
+    /// duplicate signal (repeated content alone is no proof of spam)
+    pub ban_everywhere: bool,
+}
+
+impl Default for DuplicatesConfig {
+    fn default() -> Self {
+        DuplicatesConfig {
+            window: 300,
+            min_length: 20,
+            max_distance: 3,
+            users: None,
+            chats: None,
+            weight: 1.0,
+            block_duration: 3600,
+            ban_everywhere: false,
+        }
+    }
+}
+
+/// Chats sharing their bans, so a user banned in one of them is banned in all of them
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct FederationConfig {
+    pub chats: Vec<i64>,
+}
+
+/// Recent messages kept per user and chat to clean up after punished users
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Number of messages kept per user and chat (0 to keep none)
+    pub capacity: usize,
+    /// Delete the messages a muted or banned user sent within this many seconds
+    /// (0 to only delete the offending message)
+    pub cleanup_window: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            capacity: 50,
+            cleanup_window: 600,
+        }
+    }
+}
+
+/// Notices posted to the chat when action is taken against a user
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct NoticesConfig {
+    /// Post notices at all
+    pub enabled: bool,
+    /// Language of the built-in templates (`en` or `de`)
+    pub language: String,
+    /// MarkdownV2 templates by action (`delete`, `mute`, `ban` or `flood`),
+    /// overriding the built-in templates
+    ///
+    /// The placeholders `{user}`, `{user_id}`, `{rule}`, `{action}` and
+    /// `{duration}` (in minutes) are replaced with escaped values.
+    pub templates: HashMap<String, String>,
+    /// Delete notices after this many seconds (0 to keep them)
+    pub delete_after: u64,
+}
+
+impl Default for NoticesConfig {
+    fn default() -> Self {
+        NoticesConfig {
+            enabled: true,
+            language: "en".to_string(),
+            templates: HashMap::new(),
+            delete_after: 0,
+        }
+    }
+}
+
+/// Service messages deleted automatically to keep the chat clean
+///
+/// Join messages of members banned when joining are always deleted.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ServiceMessagesConfig {
+    /// Delete messages about members joining
+    pub joins: bool,
+    /// Delete messages about members leaving or being removed
+    pub leaves: bool,
+    /// Delete messages about pinned messages
+    pub pinned: bool,
+    /// Delete messages about chat title changes
+    pub title_changes: bool,
+}
+
+impl ServiceMessagesConfig {
+    /// Returns whether any kind of service message is deleted
+    pub fn any(&self) -> bool {
+        self.joins || self.leaves || self.pinned || self.title_changes
+    }
+}
+
+/// Spam reports by members with `/report` as a reply to the spam message
+///
+/// Reports are sent to the log channel and the reported messages are added
+/// to `candidates.jsonl` in the data directory for rule authoring.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReportsConfig {
+    pub enabled: bool,
+    /// Delete a message once this many distinct trusted members reported it
+    /// (never deleted if unset)
+    pub delete_threshold: Option<usize>,
+    /// Members with at least this many recent messages in the chat are
+    /// trusted, as are admins
+    pub trusted_min_messages: usize,
+    /// Messages sent within this many seconds count as recent
+    pub trusted_window: u64,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        ReportsConfig {
+            enabled: true,
+            delete_threshold: None,
+            trusted_min_messages: 10,
+            trusted_window: 604800,
+        }
+    }
+}
+
+/// HTTP server exposing Prometheus metrics at `/metrics` and health checks at
+/// `/healthz` and `/readyz`
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Address to listen on (e.g., `127.0.0.1:9090`); the server is disabled if unset
+    pub address: Option<SocketAddr>,
+}
+
+/// Settings overriding the global settings for a single chat
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub thresholds: Option<Thresholds>,
+    pub flood: Option<FloodConfig>,
+    /// Neither share bans with nor accept bans from the other federated chats
+    pub federation_opt_out: bool,
+    pub notices: Option<NoticesConfig>,
+    pub service_messages: Option<ServiceMessagesConfig>,
+}
+
+/// Normalization stages applied to message text before obfuscated matching
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct DeobfuscationConfig {
+    /// Apply Unicode NFKC normalization (e.g., fullwidth and math letters)
+    pub nfkc: bool,
+    /// Map lookalike characters from other scripts to Latin (UTS #39)
+    pub confusables: bool,
+    /// Map digits and symbols used as letters (e.g., `cr1pt0`)
+    pub leetspeak: bool,
+    /// Convert the text to lowercase
+    pub case_fold: bool,
+}
+
+impl Default for DeobfuscationConfig {
+    fn default() -> Self {
+        DeobfuscationConfig {
+            nfkc: true,
+            confusables: true,
+            leetspeak: false,
+            case_fold: false,
+        }
+    }
+}
+
+/// Limits protecting the bot against rules with catastrophic backtracking
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RegexLimitsConfig {
+    /// Maximum number of backtracking steps for a single match
+    pub backtrack_limit: usize,
+    /// Disable a rule after it exceeded the limit this many times (0 to never disable)
+    pub disable_after: u64,
+}
+
+impl Default for RegexLimitsConfig {
+    fn default() -> Self {
+        RegexLimitsConfig {
+            backtrack_limit: 1_000_000,
+            disable_after: 0,
+        }
+    }
 }
 
-#[derive(Clone)]
 pub struct Config {
     pub telegram_bot_token: String,
     pub openai_api_key: Option<String>,
-    pub name_regexes: Vec<Regex>,
-    pub message_regexes: Vec<Regex>,
+    pub name_regexes: RuleSet,
+    pub message_regexes: RuleSet,
+    pub deobfuscation: DeobfuscationConfig,
+    pub thresholds: Thresholds,
+    pub signals: SignalsConfig,
+    pub blocked_forward_chats: Vec<i64>,
+    pub mute_duration: u64,
+    pub admin_cache_ttl: u64,
+    pub chats: HashMap<i64, ChatConfig>,
+    pub keywords: Vec<KeywordList>,
+    pub flood: FloodConfig,
+    pub duplicates: DuplicatesConfig,
+    pub federation: FederationConfig,
+    pub history: HistoryConfig,
+    pub notices: NoticesConfig,
+    pub service_messages: ServiceMessagesConfig,
+    pub reports: ReportsConfig,
+    pub http: HttpConfig,
+    pub log_channel: Option<i64>,
+    pub rule_admin_chats: Vec<i64>,
+    /// Rules added and disabled with admin commands
+    pub rule_store: RuleStore,
+    pub data_dir: Option<PathBuf>,
 }
 
 impl Config {
@@ -26,32 +539,396 @@ impl Config {
     ) -> Result<Config> {
         let file_contents = fs::read_to_string(&config_file)?;
         let regex_config: AufseherConfigFile = serde_yaml::from_str(&file_contents)?;
+        let base_dir = config_file.parent().unwrap_or(Path::new("."));
 
-        // Load user name regexes
-        let name_regexes: Vec<Regex> =
+        // Collect the rules of the config file and all included files
+        let mut sources = RuleSources::default();
+        sources.add(
+            &config_file,
+            None,
+            regex_config.name_regexes,
+            regex_config.message_regexes,
+            regex_config.keywords,
+        )?;
+        for path in resolve_includes(&regex_config.include, base_dir)? {
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read rule file '{}'", path.display()))?;
+            let rule_file: RuleFile = serde_yaml::from_str(&contents)
+                .with_context(|| format!("Failed to parse rule file '{}'", path.display()))?;
+            let namespace = match rule_file.namespace {
+                Some(namespace) => namespace,
+                None => path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+
+            info!(
+                "Including {} name rules, {} message rules and {} keyword lists from '{}' as '{}'",
+                rule_file.name_regexes.len(),
+                rule_file.message_regexes.len(),
+                rule_file.keywords.len(),
+                path.display(),
+                namespace
+            );
+            sources.add(
+                &path,
+                Some(&namespace),
+                rule_file.name_regexes,
+                rule_file.message_regexes,
+                rule_file.keywords,
+            )?;
+        }
+
+        // Reject weights that cannot be added to scores
+        let signals = &regex_config.signals;
+        for (weight, name) in [
+            (signals.llm, "signals.llm"),
+            (signals.no_username, "signals.no_username"),
+            (signals.premium, "signals.premium"),
+            (signals.new_account, "signals.new_account"),
+            (signals.blocked_forward, "signals.blocked_forward"),
+            (signals.quoted_content, "signals.quoted_content"),
+            (regex_config.duplicates.weight, "duplicates.weight"),
+        ] {
+            check_weight(weight, name)?;
+        }
+
+        // Reject notice languages without built-in templates
+        for notices in [&regex_config.notices].into_iter().chain(
             regex_config
-                .name_regexes
-                .iter()
-                .try_fold(Vec::new(), |mut acc, r| {
-                    acc.push(Regex::new(r)?);
-                    Ok::<_, fancy_regex::Error>(acc)
-                })?;
+                .chats
+                .values()
+                .filter_map(|chat| chat.notices.as_ref()),
+        ) {
+            if !notices::LANGUAGES.contains(&notices.language.as_str()) {
+                bail!(
+                    "Unsupported notice language '{}' (supported: {})",
+                    notices.language,
+                    notices::LANGUAGES.join(", ")
+                );
+            }
+        }
+
+        // Load user name regexes
+        let name_regexes = RuleSet::new("name", &sources.name_regexes, &regex_config.regex_limits)?;
 
         // Load message regexes
-        let message_regexes: Vec<Regex> =
-            regex_config
-                .message_regexes
-                .iter()
-                .try_fold(Vec::new(), |mut acc, r| {
-                    acc.push(Regex::new(r)?);
-                    Ok::<_, fancy_regex::Error>(acc)
-                })?;
+        let message_regexes = RuleSet::new(
+            "message",
+            &sources.message_regexes,
+            &regex_config.regex_limits,
+        )?;
+
+        // Load keyword lists, resolving keyword files relative to the file
+        // that defines the list
+        let keywords = sources
+            .keywords
+            .iter()
+            .enumerate()
+            .map(|(index, (list_config, path))| {
+                KeywordList::new(index, list_config, path.parent().unwrap_or(Path::new(".")))
+            })
+            .collect::<Result<Vec<KeywordList>>>()?;
+
+        // Load the rules added with admin commands on top of the rules of the config file
+        let data_dir = regex_config
+            .data_dir
+            .map(|data_dir| base_dir.join(data_dir));
+        let rule_store = RuleStore::load(data_dir.as_deref(), &regex_config.regex_limits)?;
 
         Ok(Config {
             telegram_bot_token: token,
             openai_api_key,
             name_regexes,
             message_regexes,
+            deobfuscation: regex_config.deobfuscation,
+            thresholds: regex_config.thresholds,
+            signals: regex_config.signals,
+            blocked_forward_chats: regex_config.blocked_forward_chats,
+            mute_duration: regex_config.mute_duration,
+            admin_cache_ttl: regex_config.admin_cache_ttl,
+            chats: regex_config.chats,
+            keywords,
+            flood: regex_config.flood,
+            duplicates: regex_config.duplicates,
+            federation: regex_config.federation,
+            history: regex_config.history,
+            notices: regex_config.notices,
+            service_messages: regex_config.service_messages,
+            reports: regex_config.reports,
+            http: regex_config.http,
+            log_channel: regex_config.log_channel,
+            rule_admin_chats: regex_config.rule_admin_chats,
+            rule_store,
+            data_dir,
         })
     }
+
+    /// Returns whether a rule or keyword list with the ID is defined in the config files
+    pub fn has_rule(&self, id: &str) -> bool {
+        self.name_regexes.contains(id)
+            || self.message_regexes.contains(id)
+            || self.keywords.iter().any(|list| list.id == id)
+    }
+
+    /// Returns how many seconds the messages of a user are kept after their
+    /// last message, for cleaning up after them and for trusting their reports
+    pub fn history_retention(&self) -> u64 {
+        if self.reports.enabled {
+            self.history.cleanup_window.max(self.reports.trusted_window)
+        }
+        else {
+            self.history.cleanup_window
+        }
+    }
+
+    /// Returns the action thresholds for a chat
+    pub fn thresholds_for(&self, chat_id: ChatId) -> &Thresholds {
+        self.chats
+            .get(&chat_id.0)
+            .and_then(|chat| chat.thresholds.as_ref())
+            .unwrap_or(&self.thresholds)
+    }
+
+    /// Returns whether a chat takes part in the federation
+    pub fn is_federated(&self, chat_id: ChatId) -> bool {
+        self.federation.chats.contains(&chat_id.0)
+            && !self
+                .chats
+                .get(&chat_id.0)
+                .is_some_and(|chat| chat.federation_opt_out)
+    }
+
+    /// Returns the other chats a ban in a chat is propagated to
+    pub fn federated_chats_except(&self, chat_id: ChatId) -> Vec<ChatId> {
+        if !self.is_federated(chat_id) {
+            return Vec::new();
+        }
+        self.federation
+            .chats
+            .iter()
+            .map(|&id| ChatId(id))
+            .filter(|&id| id != chat_id && self.is_federated(id))
+            .collect()
+    }
+
+    /// Returns the notice settings for a chat
+    pub fn notices_for(&self, chat_id: ChatId) -> &NoticesConfig {
+        self.chats
+            .get(&chat_id.0)
+            .and_then(|chat| chat.notices.as_ref())
+            .unwrap_or(&self.notices)
+    }
+
+    /// Returns the service messages deleted in a chat
+    pub fn service_messages_for(&self, chat_id: ChatId) -> &ServiceMessagesConfig {
+        self.chats
+            .get(&chat_id.0)
+            .and_then(|chat| chat.service_messages.as_ref())
+            .unwrap_or(&self.service_messages)
+    }
+
+    /// Returns the flood limits for a chat
+    pub fn flood_for(&self, chat_id: ChatId) -> &FloodConfig {
+        self.chats
+            .get(&chat_id.0)
+            .and_then(|chat| chat.flood.as_ref())
+            .unwrap_or(&self.flood)
+    }
+}
+
+/// Expands the `include:` entries into a sorted list of files
+///
+/// Entries are resolved relative to the directory of the config file. A
+/// directory includes all `.yaml` and `.yml` files in it.
+fn resolve_includes(includes: &[String], base_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for include in includes {
+        let path = base_dir.join(include);
+        let patterns = if path.is_dir() {
+            vec![path.join("*.yaml"), path.join("*.yml")]
+        }
+        else {
+            vec![path]
+        };
+
+        let mut matched = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.to_string_lossy();
+            for entry in glob::glob(&pattern)
+                .with_context(|| format!("Invalid include pattern '{}'", include))?
+            {
+                matched.push(entry?);
+            }
+        }
+        if matched.is_empty() {
+            warn!("Include '{}' did not match any files", include);
+        }
+
+        // Include files in a predictable order, regardless of the file system
+        matched.sort();
+        paths.extend(matched);
+    }
+    Ok(paths)
+}
+
+/// Rules and keyword lists of all config files with their final IDs
+#[derive(Default)]
+struct RuleSources {
+    name_regexes: Vec<RuleConfig>,
+    message_regexes: Vec<RuleConfig>,
+    /// Keyword lists with the file they were defined in
+    keywords: Vec<(KeywordListConfig, PathBuf)>,
+    /// File each rule or keyword list ID was defined in
+    origins: HashMap<String, PathBuf>,
+}
+
+impl RuleSources {
+    /// Adds the rules of a file, prefixing their IDs with the namespace and
+    /// rejecting IDs that are already in use
+    ///
+    /// Rules and keyword lists share one ID space, since a score counts each
+    /// ID only once.
+    fn add(
+        &mut self,
+        path: &Path,
+        namespace: Option<&str>,
+        name_regexes: Vec<RuleConfig>,
+        message_regexes: Vec<RuleConfig>,
+        keywords: Vec<KeywordListConfig>,
+    ) -> Result<()> {
+        for (index, mut rule) in name_regexes.into_iter().enumerate() {
+            let id = self.claim_id(path, namespace, rule.id, "name", index)?;
+            check_weight(rule.weight, &format!("rule '{}'", id))?;
+            rule.id = Some(id);
+            self.name_regexes.push(rule);
+        }
+        for (index, mut rule) in message_regexes.into_iter().enumerate() {
+            let id = self.claim_id(path, namespace, rule.id, "message", index)?;
+            check_weight(rule.weight, &format!("rule '{}'", id))?;
+            rule.id = Some(id);
+            self.message_regexes.push(rule);
+        }
+        for (index, mut list) in keywords.into_iter().enumerate() {
+            let id = self.claim_id(path, namespace, list.id, "keywords", index)?;
+            check_weight(list.weight, &format!("keyword list '{}'", id))?;
+            list.id = Some(id);
+            self.keywords.push((list, path.to_path_buf()));
+        }
+        Ok(())
+    }
+
+    fn claim_id(
+        &mut self,
+        path: &Path,
+        namespace: Option<&str>,
+        id: Option<String>,
+        kind: &str,
+        index: usize,
+    ) -> Result<String> {
+        let id = id.unwrap_or_else(|| format!("{}-{}", kind, index + 1));
+        let id = match namespace {
+            Some(namespace) => format!("{}/{}", namespace, id),
+            None => id,
+        };
+
+        if let Some(origin) = self.origins.get(&id) {
+            bail!(
+                "Rule ID '{}' in '{}' is already defined in '{}'",
+                id,
+                path.display(),
+                origin.display()
+            );
+        }
+        self.origins.insert(id.clone(), path.to_path_buf());
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::ChatId;
+
+    use crate::{config::Config, testing::TempDir};
+
+    #[test]
+    fn test_includes() {
+        let dir = TempDir::new("includes");
+        let config_file = dir.write(
+            "aufseher.yaml",
+            "include: [aufseher.d]\nmessage_regexes: ['local']\n",
+        );
+        dir.write(
+            "aufseher.d/community.yaml",
+            "message_regexes: ['spam', {pattern: 'scam', id: scam}]\nkeywords: [{keywords: [airdrop]}]\n",
+        );
+        dir.write(
+            "aufseher.d/other.yml",
+            "namespace: extra\nname_regexes: ['bot']\n",
+        );
+
+        // Included rules are namespaced by the file name or the namespace key
+        let config = Config::new(String::new(), None, config_file.clone()).unwrap();
+        let matched: Vec<&str> = config
+            .message_regexes
+            .matches("local spam scam")
+            .unwrap()
+            .iter()
+            .map(|rule| rule.id.as_str())
+            .collect();
+        assert_eq!(
+            matched,
+            ["message-1", "community/message-1", "community/scam"]
+        );
+        assert_eq!(config.keywords[0].id, "community/keywords-1");
+        assert_eq!(
+            config.name_regexes.matches("bot").unwrap()[0].id,
+            "extra/name-1"
+        );
+
+        // Two files in the same namespace produce conflicting IDs
+        dir.write(
+            "aufseher.d/duplicate.yaml",
+            "namespace: community\nmessage_regexes: [{pattern: 'fraud', id: scam}]\n",
+        );
+        let error = Config::new(String::new(), None, config_file).err().unwrap();
+        assert!(error.to_string().contains("'community/scam'"));
+    }
+
+    #[test]
+    fn test_federation() {
+        let config = Config::from_yaml_str(
+            "federation: {chats: [1, 2, 3]}\nchats: {3: {federation_opt_out: true}}\n",
+        );
+
+        // Bans propagate to the other federated chats that did not opt out
+        assert_eq!(config.federated_chats_except(ChatId(1)), [ChatId(2)]);
+        assert!(config.federated_chats_except(ChatId(3)).is_empty());
+        assert!(!config.is_federated(ChatId(4)));
+    }
+
+    #[test]
+    fn test_invalid_weights() {
+        let dir = TempDir::new("weights");
+        for (yaml, name) in [
+            (
+                "message_regexes: [{pattern: spam, id: spam, weight: .nan}]\n",
+                "rule 'spam'",
+            ),
+            (
+                "keywords: [{id: words, weight: .inf, keywords: [spam]}]\n",
+                "keyword list 'words'",
+            ),
+            ("duplicates: {weight: -.inf}\n", "duplicates.weight"),
+            ("signals: {llm: .nan}\n", "signals.llm"),
+        ] {
+            let config_file = dir.write("aufseher.yaml", yaml);
+            let error = Config::new(String::new(), None, config_file).err().unwrap();
+            assert!(error.to_string().ends_with(name), "{}", error);
+        }
+
+        // Negative weights are allowed
+        Config::from_yaml_str("message_regexes: [{pattern: hello, weight: -0.5}]\n");
+    }
 }
@@ -0,0 +1,248 @@
+use teloxide::types::{Message, MessageEntity, MessageEntityKind, MessageKind, MessageOrigin};
+
+/// Whether a field was written by the sender or quotes another message
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FieldKind {
+    /// Text, caption, link preview and URLs of the message itself
+    Own,
+    /// Quote and external reply, which the sender may be replying to
+    Quoted,
+}
+
+/// A single text field extracted from a message
+pub struct DocumentField {
+    pub name: &'static str,
+    pub kind: FieldKind,
+    pub text: String,
+}
+
+/// All text fields of a message that message rules are matched against
+///
+/// Spammers often split their payload across the text, a quote, a link
+/// preview and an external reply, so each field is checked on its own and all
+/// fields are also checked combined. Quoted fields are content of other
+/// messages, so they are kept apart from the sender's own fields.
+#[derive(Default)]
+pub struct MessageDocument {
+    pub fields: Vec<DocumentField>,
+}
+
+impl MessageDocument {
+    pub fn from_message(message: &Message) -> MessageDocument {
+        let mut document = MessageDocument::default();
+
+        // Text and caption with the URLs behind TextLink entities
+        // (regular URLs are already in the text)
+        if let Some(text) = message.text() {
+            document.push(FieldKind::Own, "text", text);
+            document.push_entity_urls(FieldKind::Own, message.entities());
+        }
+        if let Some(caption) = message.caption() {
+            document.push(FieldKind::Own, "caption", caption);
+            document.push_entity_urls(FieldKind::Own, message.caption_entities());
+        }
+
+        // URL of the link preview, which may differ from the links in the text
+        if let Some(link_preview_options) = message.link_preview_options()
+            && let Some(url) = &link_preview_options.url
+        {
+            document.push(FieldKind::Own, "link preview", url);
+        }
+
+        // Quoted part of the message being replied to
+        if let Some(quote) = message.quote() {
+            document.push(FieldKind::Quoted, "quote", &quote.text);
+            document.push_entity_urls(FieldKind::Quoted, Some(&quote.entities));
+        }
+
+        // Message from another chat being replied to
+        if let MessageKind::Common(message_common) = &message.kind
+            && let Some(external_reply) = &message_common.external_reply
+        {
+            match &external_reply.origin {
+                MessageOrigin::User {
+                    sender_user,
+                    ..
+                } => document.push(
+                    FieldKind::Quoted,
+                    "external reply origin",
+                    &sender_user.full_name(),
+                ),
+                MessageOrigin::HiddenUser {
+                    sender_user_name,
+                    ..
+                } => document.push(
+                    FieldKind::Quoted,
+                    "external reply origin",
+                    sender_user_name.as_str(),
+                ),
+                MessageOrigin::Chat {
+                    sender_chat: chat,
+                    ..
+                }
+                | MessageOrigin::Channel {
+                    chat,
+                    ..
+                } => {
+                    if let Some(title) = chat.title() {
+                        document.push(FieldKind::Quoted, "external reply origin", title);
+                    }
+                }
+            }
+            if let Some(chat) = &external_reply.chat
+                && let Some(title) = chat.title()
+            {
+                document.push(FieldKind::Quoted, "external reply chat", title);
+            }
+            if let Some(link_preview_options) = &external_reply.link_preview_options
+                && let Some(url) = &link_preview_options.url
+            {
+                document.push(FieldKind::Quoted, "external reply link preview", url);
+            }
+        }
+
+        document
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the fields of the given kind
+    pub fn fields_of(&self, kind: FieldKind) -> impl Iterator<Item = &DocumentField> {
+        self.fields.iter().filter(move |field| field.kind == kind)
+    }
+
+    /// Returns the text of all fields joined by newlines
+    pub fn combined_text(&self) -> String {
+        join_fields(self.fields.iter())
+    }
+
+    /// Returns the text of the sender's own fields joined by newlines
+    pub fn own_text(&self) -> String {
+        join_fields(self.fields_of(FieldKind::Own))
+    }
+
+    fn push(&mut self, kind: FieldKind, name: &'static str, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.fields.push(DocumentField {
+            name,
+            kind,
+            text: text.to_string(),
+        });
+    }
+
+    fn push_entity_urls(&mut self, kind: FieldKind, entities: Option<&[MessageEntity]>) {
+        for entity in entities.unwrap_or_default() {
+            if let MessageEntityKind::TextLink {
+                url,
+            } = &entity.kind
+            {
+                self.push(kind, "URL", url.as_str());
+            }
+        }
+    }
+}
+
+fn join_fields<'a>(fields: impl Iterator<Item = &'a DocumentField>) -> String {
+    fields
+        .map(|field| field.text.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::Message;
+
+    use crate::document::MessageDocument;
+
+    fn fields(json: &str) -> Vec<(&'static str, String)> {
+        let message: Message = serde_json::from_str(json).unwrap();
+        MessageDocument::from_message(&message)
+            .fields
+            .into_iter()
+            .map(|field| (field.name, field.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_message() {
+        // Caption with a hidden link
+        let fields_of_photo = fields(
+            r#"{
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": -100, "type": "supergroup", "title": "Group"},
+                "from": {"id": 1, "is_bot": false, "first_name": "Mallory"},
+                "photo": [{"file_id": "a", "file_unique_id": "b", "width": 1, "height": 1}],
+                "caption": "Click here",
+                "caption_entities": [
+                    {"type": "text_link", "offset": 0, "length": 5, "url": "https://spam.example/"}
+                ]
+            }"#,
+        );
+        assert_eq!(
+            fields_of_photo,
+            vec![
+                ("caption", "Click here".to_string()),
+                ("URL", "https://spam.example/".to_string()),
+            ]
+        );
+
+        // Reply to a message from another chat, quoting it, with a link preview
+        let fields_of_reply = fields(
+            r#"{
+                "message_id": 2,
+                "date": 0,
+                "chat": {"id": -100, "type": "supergroup", "title": "Group"},
+                "from": {"id": 1, "is_bot": false, "first_name": "Mallory"},
+                "text": "Look",
+                "link_preview_options": {"is_disabled": false, "url": "https://preview.example/"},
+                "quote": {"text": "Free crypto", "position": 0},
+                "external_reply": {
+                    "origin": {
+                        "type": "user",
+                        "date": 0,
+                        "sender_user": {"id": 2, "is_bot": false, "first_name": "Crypto", "last_name": "Giveaway"}
+                    },
+                    "chat": {"id": -200, "type": "channel", "title": "Airdrops"},
+                    "message_id": 5,
+                    "link_preview_options": {"is_disabled": false, "url": "https://airdrop.example/"},
+                    "photo": [{"file_id": "a", "file_unique_id": "b", "width": 1, "height": 1}]
+                }
+            }"#,
+        );
+        assert_eq!(
+            fields_of_reply,
+            vec![
+                ("text", "Look".to_string()),
+                ("link preview", "https://preview.example/".to_string()),
+                ("quote", "Free crypto".to_string()),
+                ("external reply origin", "Crypto Giveaway".to_string()),
+                ("external reply chat", "Airdrops".to_string()),
+                (
+                    "external reply link preview",
+                    "https://airdrop.example/".to_string()
+                ),
+            ]
+        );
+
+        let message: Message = serde_json::from_str(
+            r#"{
+                "message_id": 3,
+                "date": 0,
+                "chat": {"id": -100, "type": "supergroup", "title": "Group"},
+                "text": "Earn",
+                "quote": {"text": "daily", "position": 0}
+            }"#,
+        )
+        .unwrap();
+        let document = MessageDocument::from_message(&message);
+        assert_eq!(document.combined_text(), "Earn\ndaily");
+        assert_eq!(document.own_text(), "Earn");
+    }
+}
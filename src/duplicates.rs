@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::DuplicatesConfig, hash::fnv1a};
+
+// Number of characters in each shingle used for the simhash
+const SHINGLE_LENGTH: usize = 4;
+
+/// Fingerprint of the normalized text of a message
+///
+/// The hash detects identical content, while the simhash also detects
+/// near-duplicates, since similar texts have simhashes that differ in only a
+/// few bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub hash: u64,
+    pub simhash: u64,
+}
+
+impl Fingerprint {
+    /// Fingerprints already deobfuscated text, ignoring case, whitespace,
+    /// punctuation and emoji
+    pub fn new(deobfuscated_text: &str) -> Fingerprint {
+        let normalized: Vec<char> = deobfuscated_text
+            .chars()
+            .flat_map(char::to_lowercase)
+            .filter(|character| character.is_alphanumeric())
+            .collect();
+
+        // Every bit of the simhash is set if most shingle hashes have it set
+        let mut bit_weights = [0i32; 64];
+        for shingle in normalized.windows(SHINGLE_LENGTH.min(normalized.len()).max(1)) {
+            let hash = fnv1a(&shingle.iter().collect::<String>());
+            for (bit, weight) in bit_weights.iter_mut().enumerate() {
+                *weight += if hash >> bit & 1 == 1 { 1 } else { -1 };
+            }
+        }
+        let simhash = bit_weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |simhash, (bit, _)| simhash | 1 << bit);
+
+        Fingerprint {
+            hash: fnv1a(&normalized.iter().collect::<String>()),
+            simhash,
+        }
+    }
+
+    pub fn is_similar(&self, other: &Fingerprint, max_distance: u32) -> bool {
+        self.hash == other.hash || (self.simhash ^ other.simhash).count_ones() <= max_distance
+    }
+}
+
+/// A message whose fingerprint was recorded
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Posting {
+    /// Unix timestamp of the message
+    pub time: i64,
+    pub fingerprint: Fingerprint,
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub message_id: i32,
+}
+
+/// A fingerprint that is temporarily treated as spam
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BlockedFingerprint {
+    fingerprint: Fingerprint,
+    /// Unix timestamp at which the fingerprint is unblocked
+    until: i64,
+}
+
+pub enum DuplicateVerdict {
+    /// The content was not posted often enough to be considered spam
+    None,
+    /// The content matches a blocked fingerprint
+    Blocked,
+    /// The content was posted by too many users or in too many chats and has
+    /// been blocked; contains the earlier postings of the content
+    Escalated(Vec<Posting>),
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct FingerprintState {
+    recent: VecDeque<Posting>,
+    blocklist: Vec<BlockedFingerprint>,
+}
+
+/// Fingerprints of recent messages in all chats, used to detect spam waves
+/// posting the same content by different users or in different chats
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FingerprintStore {
+    state: Mutex<FingerprintState>,
+}
+
+impl FingerprintStore {
+    /// Records a message and checks whether its content was posted by too many
+    /// users or in too many chats within the window
+    pub fn record(&self, posting: Posting, config: &DuplicatesConfig) -> DuplicateVerdict {
+        let mut state = self.state.lock().unwrap();
+        let window_start = posting.time - config.window as i64;
+        while state
+            .recent
+            .front()
+            .is_some_and(|first| first.time <= window_start)
+        {
+            state.recent.pop_front();
+        }
+        state
+            .blocklist
+            .retain(|blocked| blocked.until > posting.time);
+
+        let is_similar = |fingerprint: &Fingerprint| {
+            fingerprint.is_similar(&posting.fingerprint, config.max_distance)
+        };
+        if state
+            .blocklist
+            .iter()
+            .any(|blocked| is_similar(&blocked.fingerprint))
+        {
+            return DuplicateVerdict::Blocked;
+        }
+
+        // Count the distinct users and chats that posted the content, including this message
+        let similar: Vec<Posting> = state
+            .recent
+            .iter()
+            .filter(|earlier| is_similar(&earlier.fingerprint))
+            .copied()
+            .collect();
+        let users: HashSet<u64> = similar
+            .iter()
+            .map(|earlier| earlier.user_id)
+            .chain([posting.user_id])
+            .collect();
+        let chats: HashSet<i64> = similar
+            .iter()
+            .map(|earlier| earlier.chat_id)
+            .chain([posting.chat_id])
+            .collect();
+        let reached =
+            |limit: Option<usize>, count: usize| limit.is_some_and(|limit| count >= limit);
+
+        if !reached(config.users, users.len()) && !reached(config.chats, chats.len()) {
+            state.recent.push_back(posting);
+            return DuplicateVerdict::None;
+        }
+
+        // Block the content and forget the earlier postings, which are handled now
+        state.blocklist.push(BlockedFingerprint {
+            fingerprint: posting.fingerprint,
+            until: posting.time + config.block_duration as i64,
+        });
+        state
+            .recent
+            .retain(|earlier| !is_similar(&earlier.fingerprint));
+        DuplicateVerdict::Escalated(similar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::DuplicatesConfig,
+        duplicates::{DuplicateVerdict, Fingerprint, FingerprintStore, Posting},
+    };
+
+    fn posting(time: i64, text: &str, chat_id: i64, user_id: u64) -> Posting {
+        Posting {
+            time,
+            fingerprint: Fingerprint::new(text),
+            chat_id,
+            user_id,
+            message_id: time as i32,
+        }
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let spam = Fingerprint::new("Earn $500 daily with crypto, DM me now for the details!");
+
+        // Case, whitespace and punctuation are ignored
+        let same = Fingerprint::new("earn 500 daily with crypto dm me now for the details");
+        assert_eq!(spam.hash, same.hash);
+
+        // Small changes are detected as near-duplicates
+        let similar = Fingerprint::new("Earn $600 daily with crypto, DM me now for the details!!");
+        assert_ne!(spam.hash, similar.hash);
+        assert!(spam.is_similar(&similar, 8));
+
+        let different = Fingerprint::new("Has anyone tried the new release of the bot yet?");
+        assert!(!spam.is_similar(&different, 8));
+    }
+
+    #[test]
+    fn test_fingerprint_store() {
+        let store = FingerprintStore::default();
+        let config = DuplicatesConfig {
+            chats: Some(3),
+            ..DuplicatesConfig::default()
+        };
+        let text = "Join my channel for free crypto signals";
+
+        // The same content in two chats is fine, but the third chat escalates
+        assert!(matches!(
+            store.record(posting(0, text, 1, 1), &config),
+            DuplicateVerdict::None
+        ));
+        assert!(matches!(
+            store.record(posting(1, text, 2, 2), &config),
+            DuplicateVerdict::None
+        ));
+        match store.record(posting(2, text, 3, 3), &config) {
+            DuplicateVerdict::Escalated(earlier) => assert_eq!(earlier.len(), 2),
+            _ => panic!("content posted in three chats was not escalated"),
+        }
+
+        // The content stays blocked for the block duration
+        assert!(matches!(
+            store.record(posting(3, text, 4, 4), &config),
+            DuplicateVerdict::Blocked
+        ));
+        let expired = config.block_duration as i64 + 3;
+        assert!(matches!(
+            store.record(posting(expired, text, 4, 4), &config),
+            DuplicateVerdict::None
+        ));
+    }
+}
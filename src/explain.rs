@@ -0,0 +1,295 @@
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{MessageKind, User},
+};
+
+use crate::{
+    actions::Action,
+    config::{Config, Thresholds},
+    document::MessageDocument,
+    matching, openai,
+    scoring::Score,
+    settings::ChatSettings,
+    state::State,
+};
+
+// Telegram rejects messages longer than 4096 characters
+const MAX_REPORT_LENGTH: usize = 4096;
+
+/// Runs all checks against a message without taking any action and
+/// describes which rules matched and what the bot would have done, taking the
+/// settings of the chat into account
+pub async fn explain_message(
+    bot: &Bot,
+    state: &State,
+    target: &Message,
+    config: &Config,
+) -> Result<String> {
+    let mut explanation = Explanation::default();
+    let thresholds = config.thresholds_for(target.chat.id);
+    let settings = state.settings.get(target.chat.id);
+
+    // New chat members are scored by their names only
+    if let MessageKind::NewChatMembers(message_new_chat_members) = &target.kind {
+        for member in &message_new_chat_members.new_chat_members {
+            let mut score = Score::default();
+            score.add_name("member name", &member.full_name(), config)?;
+            score.add_user(member, config);
+
+            explanation
+                .lines
+                .push(format!("New member: '{}'", member.full_name()));
+            let action = settings.limit_action(thresholds.action_for(score.total()));
+            let exemption = check_exemption(bot, state, target, member, action).await?;
+            explanation.push_score(&score, thresholds, action, exemption);
+        }
+        return Ok(explanation.finish());
+    }
+
+    // Score the sender/forwarder names and the trustworthiness of the sender
+    let mut score = Score::default();
+    score.add_message_names(target, config)?;
+    if let Some(user) = &target.from {
+        score.add_user(user, config);
+    }
+
+    // Score every field of the message on its own and combined
+    let document = MessageDocument::from_message(target);
+    for field in &document.fields {
+        explanation.push_deobfuscation(field.name, &field.text, config);
+    }
+    score.add_document(&document, config)?;
+    let own_text = document.own_text();
+    if !own_text.is_empty() {
+        explanation
+            .check_llm(&own_text, &mut score, thresholds, &settings, config)
+            .await;
+    }
+
+    let action = settings.limit_action(thresholds.action_for(score.total()));
+    let exemption = match &target.from {
+        Some(user) => check_exemption(bot, state, target, user, action).await?,
+        None => None,
+    };
+    explanation.push_score(&score, thresholds, action, exemption);
+
+    Ok(explanation.finish())
+}
+
+/// Runs the message text checks against a piece of text without taking any
+/// action, as if it was sent to the chat
+pub async fn explain_text(
+    text: &str,
+    chat_id: ChatId,
+    state: &State,
+    config: &Config,
+) -> Result<String> {
+    let mut explanation = Explanation::default();
+    let thresholds = config.thresholds_for(chat_id);
+    let settings = state.settings.get(chat_id);
+
+    let mut score = Score::default();
+    explanation.push_deobfuscation("text", text, config);
+    score.add_text("text", text, config)?;
+    explanation
+        .check_llm(text, &mut score, thresholds, &settings, config)
+        .await;
+
+    let action = settings.limit_action(thresholds.action_for(score.total()));
+    let exemption = shadow_mode_exemption(&settings, action);
+    explanation.push_score(&score, thresholds, action, exemption);
+
+    Ok(explanation.finish())
+}
+
+// Admins and owners are never punished, and chats in shadow mode only log actions
+async fn check_exemption(
+    bot: &Bot,
+    state: &State,
+    target: &Message,
+    user: &User,
+    action: Action,
+) -> Result<Option<&'static str>> {
+    let settings = state.settings.get(target.chat.id);
+    if let Some(exemption) = shadow_mode_exemption(&settings, action) {
+        return Ok(Some(exemption));
+    }
+    if action == Action::None {
+        return Ok(None);
+    }
+
+    if state.admins.is_admin(bot, target.chat.id, user.id).await? {
+        return Ok(Some("the user is an admin or creator"));
+    }
+    Ok(None)
+}
+
+fn shadow_mode_exemption(settings: &ChatSettings, action: Action) -> Option<&'static str> {
+    if settings.shadow_mode && action != Action::None {
+        return Some("the chat is in shadow mode");
+    }
+    None
+}
+
+#[derive(Default)]
+struct Explanation {
+    lines: Vec<String>,
+}
+
+impl Explanation {
+    fn push_deobfuscation(&mut self, field: &str, text: &str, config: &Config) {
+        self.lines.push(format!("Message {}: '{}'", field, text));
+
+        // Show the output of every deobfuscation stage
+        for (stage, output) in
+            matching::deobfuscate_message_text_stages(text, &config.deobfuscation)
+        {
+            self.lines
+                .push(format!("Deobfuscation ({}): '{}'", stage, output));
+        }
+
+        self.lines.push(String::new());
+    }
+
+    async fn check_llm(
+        &mut self,
+        text: &str,
+        score: &mut Score,
+        thresholds: &Thresholds,
+        settings: &ChatSettings,
+        config: &Config,
+    ) {
+        // Ask the LLM for its verdict if an API key is configured and enabled in
+        // the chat, unless the score is already high enough for a ban
+        if !settings.llm_enabled() {
+            self.lines
+                .push("LLM verdict: skipped (disabled in this chat)".to_string());
+        }
+        else if thresholds.action_for(score.total()) == Action::Ban {
+            self.lines.push(
+                "LLM verdict: skipped (the score already reaches the ban threshold)".to_string(),
+            );
+        }
+        else if let Some(openai_api_key) = &config.openai_api_key {
+            match openai::openai_check_is_message_spam(text, openai_api_key).await {
+                Ok(true) => {
+                    self.lines.push("LLM verdict: spam".to_string());
+                    score.add_signal("llm", config.signals.llm);
+                }
+                Ok(false) => self.lines.push("LLM verdict: not spam".to_string()),
+                Err(error) => self.lines.push(format!("LLM verdict: error ({})", error)),
+            }
+        }
+        else {
+            self.lines
+                .push("LLM verdict: skipped (no API key)".to_string());
+        }
+
+        self.lines.push(String::new());
+    }
+
+    fn push_score(
+        &mut self,
+        score: &Score,
+        thresholds: &Thresholds,
+        action: Action,
+        exemption: Option<&str>,
+    ) {
+        if score.signals.is_empty() {
+            self.lines.push("Signals: none".to_string());
+        }
+        else {
+            self.lines.push("Signals:".to_string());
+        }
+        for signal in &score.signals {
+            match &signal.rule_id {
+                Some(rule_id) => self.lines.push(format!(
+                    "- {}: rule '{}' (weight {})",
+                    signal.source, rule_id, signal.weight
+                )),
+                None => self
+                    .lines
+                    .push(format!("- {} (weight {})", signal.source, signal.weight)),
+            }
+        }
+
+        let format_threshold =
+            |threshold: Option<f64>| threshold.map_or("-".to_string(), |value| value.to_string());
+        self.lines.push(format!("Score: {}", score.total()));
+        self.lines.push(format!(
+            "Thresholds: delete {}, mute {}, ban {}",
+            format_threshold(thresholds.delete),
+            format_threshold(thresholds.mute),
+            format_threshold(thresholds.ban)
+        ));
+
+        match exemption {
+            Some(reason) => self
+                .lines
+                .push(format!("Action: none ({} instead, but {})", action, reason)),
+            None => self.lines.push(format!("Action: {}", action)),
+        }
+        self.lines.push(String::new());
+    }
+
+    fn finish(self) -> String {
+        truncate_report(self.lines.join("\n").trim_end().to_string())
+    }
+}
+
+/// Shortens a report to the maximum length of a Telegram message
+pub fn truncate_report(report: String) -> String {
+    if report.chars().count() > MAX_REPORT_LENGTH {
+        let mut truncated: String = report.chars().take(MAX_REPORT_LENGTH - 1).collect();
+        truncated.push('…');
+        return truncated;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::ChatId;
+
+    use crate::{
+        config::Config,
+        explain::{self, MAX_REPORT_LENGTH},
+        settings::Setting,
+        state::State,
+    };
+
+    #[tokio::test]
+    async fn test_explain_text() {
+        let config = Config::from_yaml_str(
+            "message_regexes: [{pattern: '(?i)casino', id: casino, weight: 0.6}]\nthresholds: {delete: 0.5, ban: 1.0}\n",
+        );
+        let state = State::default();
+        let chat_id = ChatId(-100);
+
+        let report = explain::explain_text("Best CASINO", chat_id, &state, &config)
+            .await
+            .unwrap();
+        assert!(report.starts_with("Message text: 'Best CASINO'\n"));
+        assert!(report.contains("LLM verdict: skipped (no API key)"));
+        assert!(report.contains("- message text: rule 'casino' (weight 0.6)"));
+        assert!(report.contains("Score: 0.6\nThresholds: delete 0.5, mute -, ban 1"));
+        assert!(report.ends_with("Action: delete"));
+
+        // Chats in shadow mode only log the action
+        state.settings.toggle(chat_id, Setting::ShadowMode, &config);
+        let report = explain::explain_text("Best CASINO", chat_id, &state, &config)
+            .await
+            .unwrap();
+        assert!(report.ends_with("Action: none (delete instead, but the chat is in shadow mode)"));
+    }
+
+    #[test]
+    fn test_truncate_report() {
+        assert_eq!(explain::truncate_report("short".to_string()), "short");
+
+        let truncated = explain::truncate_report("ä".repeat(MAX_REPORT_LENGTH + 1));
+        assert_eq!(truncated.chars().count(), MAX_REPORT_LENGTH);
+        assert!(truncated.ends_with("ä…"));
+    }
+}
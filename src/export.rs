@@ -0,0 +1,358 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    modlog::{MODERATION_LOG_FILE, ModerationEntry},
+    rule_store::RuleKind,
+};
+
+/// Output format of the exported corpus
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// `tests:` and `false_positives:` sections in the format of the config file
+    Yaml,
+    /// One labeled sample per line
+    Jsonl,
+}
+
+/// Names and message texts in the format of the `tests:` section of the config file
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Samples {
+    pub usernames: Vec<String>,
+    pub messages: Vec<String>,
+}
+
+impl Samples {
+    fn push(&mut self, kind: SampleKind, text: &str) {
+        let samples = match kind {
+            SampleKind::Username => &mut self.usernames,
+            SampleKind::Message => &mut self.messages,
+        };
+        if !samples.iter().any(|sample| sample == text) {
+            samples.push(text.to_string());
+        }
+    }
+}
+
+/// Spam the bot acted on and false positives that admins pardoned
+///
+/// The `tests` section can be copied into the config file, while the false
+/// positives are messages and names the rules should not match.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Corpus {
+    pub tests: Samples,
+    pub false_positives: Samples,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SampleKind {
+    Username,
+    Message,
+}
+
+/// A labeled sample of the corpus in the JSON lines format
+#[derive(Debug, Serialize)]
+struct Sample<'a> {
+    kind: SampleKind,
+    text: &'a str,
+    spam: bool,
+    time: i64,
+    chat_id: i64,
+    user_id: u64,
+    action: &'a str,
+    reason: &'a str,
+}
+
+/// Reads the moderation log from the data directory and writes the corpus to
+/// the output file or stdout
+pub fn export(config: &Config, format: ExportFormat, output: Option<&Path>) -> Result<()> {
+    let Some(data_dir) = &config.data_dir
+    else {
+        bail!("No data directory is configured, so there is no moderation log to export");
+    };
+
+    let entries = read_moderation_log(&data_dir.join(MODERATION_LOG_FILE))?;
+    let samples = label_samples(&entries);
+    let unavailable = count_unavailable_samples(&entries);
+    if unavailable > 0 {
+        warn!(
+            "{} samples are unavailable, as their texts and names were not stored because of --log-privacy",
+            unavailable
+        );
+    }
+    let contents = match format {
+        ExportFormat::Yaml => {
+            let mut corpus = Corpus::default();
+            for sample in &samples {
+                let section = if sample.spam {
+                    &mut corpus.tests
+                }
+                else {
+                    &mut corpus.false_positives
+                };
+                section.push(sample.kind, sample.text);
+            }
+            serde_yaml::to_string(&corpus)?
+        }
+        ExportFormat::Jsonl => samples
+            .iter()
+            .map(|sample| Ok(serde_json::to_string(sample)? + "\n"))
+            .collect::<Result<String>>()?,
+    };
+
+    match output {
+        Some(output) => {
+            fs::write(output, contents)
+                .with_context(|| format!("Failed to write '{}'", output.display()))?;
+            info!(
+                "Exported {} samples from {} moderation log entries to '{}'",
+                samples.len(),
+                entries.len(),
+                output.display()
+            );
+        }
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
+
+/// Reads all entries of a moderation log, skipping lines that cannot be parsed
+fn read_moderation_log(path: &Path) -> Result<Vec<ModerationEntry>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open moderation log '{}'", path.display()))?;
+
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(error) => warn!(
+                "Skipping line {} of '{}': {}",
+                index + 1,
+                path.display(),
+                error
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+/// Turns moderation log entries into labeled samples
+///
+/// Message texts and names are only taken from entries whose message rules or
+/// name rules matched them, so actions caused by other signals (e.g., the
+/// LLM, duplicates, reports or federated bans) are skipped. Actions against a
+/// user in a chat before they were pardoned there are false positives.
+fn label_samples(entries: &[ModerationEntry]) -> Vec<Sample<'_>> {
+    let mut pardons: HashMap<(i64, u64), i64> = HashMap::new();
+    for entry in entries.iter().filter(|entry| entry.action == "pardon") {
+        let time = pardons
+            .entry((entry.chat_id, entry.user_id))
+            .or_insert(entry.time);
+        *time = (*time).max(entry.time);
+    }
+
+    let mut seen = HashSet::new();
+    let mut samples = Vec::new();
+    for entry in entries {
+        if entry.action == "pardon" {
+            continue;
+        }
+        let spam = pardons
+            .get(&(entry.chat_id, entry.user_id))
+            .is_none_or(|&pardoned| pardoned < entry.time);
+
+        for &rule_kind in &entry.rule_kinds {
+            let (kind, text) = match rule_kind {
+                RuleKind::Message => (SampleKind::Message, &entry.text),
+                RuleKind::Name => (SampleKind::Username, &entry.user_name),
+            };
+            let Some(text) = text
+            else {
+                continue;
+            };
+            // Later entries for the same sample and label add nothing to the corpus
+            if text.trim().is_empty() || !seen.insert((spam, text.as_str())) {
+                continue;
+            }
+            samples.push(Sample {
+                kind,
+                text,
+                spam,
+                time: entry.time,
+                chat_id: entry.chat_id,
+                user_id: entry.user_id,
+                action: &entry.action,
+                reason: &entry.reason,
+            });
+        }
+    }
+    samples
+}
+
+/// Counts the names and texts that rules matched but that were not stored
+fn count_unavailable_samples(entries: &[ModerationEntry]) -> usize {
+    entries
+        .iter()
+        .flat_map(|entry| {
+            entry.rule_kinds.iter().filter(|kind| match kind {
+                RuleKind::Message => entry.text.is_none(),
+                RuleKind::Name => entry.user_name.is_none(),
+            })
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        export::{SampleKind, count_unavailable_samples, label_samples},
+        modlog::ModerationEntry,
+        retry::Outcome,
+        rule_store::RuleKind,
+    };
+
+    fn entry(
+        time: i64,
+        chat_id: i64,
+        user_id: u64,
+        action: &str,
+        text: Option<&str>,
+        rule_kinds: &[RuleKind],
+    ) -> ModerationEntry {
+        let mut entry = ModerationEntry::new(chat_id, user_id, action, "rule", &Outcome::default());
+        entry.time = time;
+        entry.user_name = Some(format!("User {}", user_id));
+        entry.text = text.map(str::to_string);
+        entry.rule_kinds = rule_kinds.to_vec();
+        entry
+    }
+
+    fn labels(entries: &[ModerationEntry]) -> Vec<(&str, bool)> {
+        label_samples(entries)
+            .iter()
+            .map(|sample| (sample.text, sample.spam))
+            .collect()
+    }
+
+    #[test]
+    fn test_label_samples() {
+        let message = &[RuleKind::Message];
+        let entries = [
+            entry(1, -100, 1, "ban", Some("casino bonus"), message),
+            entry(2, -100, 1, "delete", Some("casino bonus"), message),
+            entry(3, -100, 2, "delete", Some("hello everyone"), message),
+            entry(4, -100, 3, "ban", None, &[RuleKind::Name]),
+            entry(6, -100, 2, "pardon", None, &[]),
+            // Users can spam again after being pardoned
+            entry(7, -100, 2, "delete", Some("free crypto"), message),
+        ];
+
+        let samples = label_samples(&entries);
+        assert_eq!(
+            labels(&entries),
+            [
+                ("casino bonus", true),
+                ("hello everyone", false),
+                ("User 3", true),
+                ("free crypto", true),
+            ]
+        );
+        assert!(matches!(samples[2].kind, SampleKind::Username));
+    }
+
+    #[test]
+    fn test_label_samples_by_rule_kind() {
+        // Only what the rules matched is exported, not the rest of the entry
+        let entries = [
+            entry(1, -100, 1, "ban", Some("hi"), &[RuleKind::Name]),
+            entry(
+                2,
+                -100,
+                2,
+                "ban",
+                Some("cheap followers"),
+                &[RuleKind::Message],
+            ),
+            entry(
+                3,
+                -100,
+                3,
+                "ban",
+                Some("casino"),
+                &[RuleKind::Name, RuleKind::Message],
+            ),
+        ];
+        assert_eq!(
+            labels(&entries),
+            [
+                ("User 1", true),
+                ("cheap followers", true),
+                ("User 3", true),
+                ("casino", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_samples_without_rules() {
+        // Actions caused by other signals say nothing about the rules
+        let mut federated = entry(1, -100, 1, "ban", Some("hello"), &[]);
+        federated.reason = "federated ban".to_string();
+        let mut reported = entry(2, -100, 2, "delete", Some("good morning"), &[]);
+        reported.reason = "reported by 3 trusted members".to_string();
+        let entries = [
+            federated,
+            reported,
+            entry(3, -100, 3, "flood", Some("hi"), &[]),
+            entry(4, -100, 4, "ban", Some("same text as others"), &[]),
+        ];
+        assert!(labels(&entries).is_empty());
+    }
+
+    #[test]
+    fn test_unavailable_samples() {
+        // Texts and names are not stored if the privacy mode redacts them
+        let mut entry = entry(
+            1,
+            -100,
+            1,
+            "ban",
+            None,
+            &[RuleKind::Name, RuleKind::Message],
+        );
+        entry.user_name = None;
+        let entries = [entry];
+        assert!(labels(&entries).is_empty());
+        assert_eq!(count_unavailable_samples(&entries), 2);
+    }
+
+    #[test]
+    fn test_label_samples_pardons_per_chat() {
+        // A pardon only relabels the actions in the chat it was given in
+        let message = &[RuleKind::Message];
+        let entries = [
+            entry(1, -100, 1, "ban", Some("casino bonus"), message),
+            entry(2, -200, 1, "ban", Some("casino jackpot"), message),
+            entry(3, -200, 1, "pardon", None, &[]),
+        ];
+        assert_eq!(
+            labels(&entries),
+            [("casino bonus", true), ("casino jackpot", false)]
+        );
+    }
+}
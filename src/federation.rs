@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// A user banned in one of the federated chats
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedBan {
+    /// Chat the user was originally banned in
+    pub chat_id: i64,
+    /// Unix timestamp of the ban
+    pub time: i64,
+    pub reason: String,
+}
+
+/// Users banned in any federated chat, who are banned in all of them
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BanList {
+    /// Bans by user ID
+    bans: Mutex<HashMap<u64, FederatedBan>>,
+}
+
+impl BanList {
+    /// Adds a user to the list, returning false if they were already on it
+    pub fn add(&self, user_id: u64, ban: FederatedBan) -> bool {
+        let mut bans = self.bans.lock().unwrap();
+        if bans.contains_key(&user_id) {
+            return false;
+        }
+        bans.insert(user_id, ban);
+        true
+    }
+
+    pub fn get(&self, user_id: u64) -> Option<FederatedBan> {
+        self.bans.lock().unwrap().get(&user_id).cloned()
+    }
+
+    /// Removes a user from the list, returning false if they were not on it
+    pub fn remove(&self, user_id: u64) -> bool {
+        self.bans.lock().unwrap().remove(&user_id).is_some()
+    }
+}
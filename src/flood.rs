@@ -0,0 +1,191 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{MediaKind, Message, MessageEntityKind, MessageId, MessageKind};
+
+use crate::config::FloodConfig;
+
+/// A message counted towards the flood limits of its sender
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FloodEvent {
+    /// Unix timestamp of the message
+    pub time: i64,
+    pub message_id: i32,
+    pub media: bool,
+    pub link: bool,
+    pub forward: bool,
+}
+
+impl FloodEvent {
+    pub fn from_message(message: &Message) -> FloodEvent {
+        let media = match &message.kind {
+            MessageKind::Common(message_common) => {
+                !matches!(message_common.media_kind, MediaKind::Text(_))
+            }
+            _ => false,
+        };
+        let link = message
+            .entities()
+            .into_iter()
+            .chain(message.caption_entities())
+            .flatten()
+            .any(|entity| {
+                matches!(
+                    entity.kind,
+                    MessageEntityKind::Url | MessageEntityKind::TextLink { .. }
+                )
+            });
+
+        FloodEvent {
+            time: message.date.timestamp(),
+            message_id: message.id.0,
+            media,
+            link,
+            forward: message.forward_origin().is_some(),
+        }
+    }
+}
+
+/// A flood limit exceeded by a user
+#[derive(Debug)]
+pub struct FloodViolation {
+    /// Kind of messages that exceeded the limit (e.g., `messages` or `links`)
+    pub kind: &'static str,
+    pub count: usize,
+    pub limit: usize,
+    /// All messages the user sent within the window
+    pub message_ids: Vec<MessageId>,
+}
+
+/// Recent messages of every user in every chat, used to detect floods
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FloodTracker {
+    /// Events by chat ID and user ID
+    events: Mutex<HashMap<i64, HashMap<u64, VecDeque<FloodEvent>>>>,
+}
+
+impl FloodTracker {
+    /// Records a message and checks whether the user exceeded a limit within the window
+    ///
+    /// The events of a user who exceeded a limit are cleared, so the same burst
+    /// is only reported once.
+    pub fn record(
+        &self,
+        chat_id: i64,
+        user_id: u64,
+        event: FloodEvent,
+        config: &FloodConfig,
+    ) -> Option<FloodViolation> {
+        let mut events = self.events.lock().unwrap();
+        let chat_events = events.entry(chat_id).or_default();
+        let window_start = event.time - config.window as i64;
+
+        // Forget users who have not posted within the window
+        chat_events.retain(|_, user_events| {
+            user_events
+                .back()
+                .is_some_and(|last| last.time > window_start)
+        });
+
+        let user_events = chat_events.entry(user_id).or_default();
+        user_events.push_back(event);
+        while user_events
+            .front()
+            .is_some_and(|first| first.time <= window_start)
+        {
+            user_events.pop_front();
+        }
+
+        let count =
+            |kind: fn(&FloodEvent) -> bool| user_events.iter().filter(|event| kind(event)).count();
+        let counts = [
+            ("messages", config.messages, count(|_| true)),
+            ("media", config.media, count(|event| event.media)),
+            ("links", config.links, count(|event| event.link)),
+            ("forwards", config.forwards, count(|event| event.forward)),
+        ];
+        let (kind, limit, count) = counts.into_iter().find_map(|(kind, limit, count)| {
+            limit
+                .filter(|&limit| count > limit)
+                .map(|limit| (kind, limit, count))
+        })?;
+
+        let message_ids = user_events
+            .drain(..)
+            .map(|event| MessageId(event.message_id))
+            .collect();
+        Some(FloodViolation {
+            kind,
+            count,
+            limit,
+            message_ids,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::FloodConfig,
+        flood::{FloodEvent, FloodTracker},
+    };
+
+    fn event(time: i64, message_id: i32, link: bool) -> FloodEvent {
+        FloodEvent {
+            time,
+            message_id,
+            media: false,
+            link,
+            forward: false,
+        }
+    }
+
+    #[test]
+    fn test_flood_tracker() {
+        let tracker = FloodTracker::default();
+        let config = FloodConfig {
+            messages: Some(3),
+            links: Some(1),
+            ..FloodConfig::default()
+        };
+
+        // Messages spread over more than the window do not count together
+        for (index, time) in [0, 4, 8, 12, 16].into_iter().enumerate() {
+            assert!(
+                tracker
+                    .record(1, 1, event(time, index as i32, false), &config)
+                    .is_none()
+            );
+        }
+
+        // A fourth message within the window exceeds the limit
+        assert!(
+            tracker
+                .record(1, 2, event(20, 10, false), &config)
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record(1, 2, event(21, 11, false), &config)
+                .is_none()
+        );
+        assert!(
+            tracker
+                .record(1, 2, event(22, 12, false), &config)
+                .is_none()
+        );
+        let violation = tracker.record(1, 2, event(23, 13, false), &config).unwrap();
+        assert_eq!(violation.kind, "messages");
+        assert_eq!(violation.message_ids.len(), 4);
+
+        // Limits are tracked per chat and per kind
+        assert!(tracker.record(2, 2, event(24, 14, true), &config).is_none());
+        let violation = tracker.record(2, 2, event(25, 15, true), &config).unwrap();
+        assert_eq!(violation.kind, "links");
+        assert_eq!(violation.count, 2);
+    }
+}
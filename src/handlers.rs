@@ -1,26 +1,85 @@
 use anyhow::Result;
+use chrono::Utc;
 use teloxide::{
     prelude::*,
-    types::{MediaKind, MessageEntityKind, MessageKind, MessageNewChatMembers, UpdateKind, User},
+    types::{
+        CallbackQuery, ChatMemberUpdated, MediaKind, MessageId, MessageKind, MessageNewChatMembers,
+        ReplyParameters, UpdateKind, User,
+    },
 };
 use tracing::{debug, info, warn};
 
-use crate::{actions, config::Config, matching, openai};
+use crate::{
+    actions::{self, Action},
+    config::{Config, Thresholds},
+    document::MessageDocument,
+    duplicates::{DuplicateVerdict, Fingerprint, Posting},
+    explain,
+    federation::FederatedBan,
+    flood::FloodEvent,
+    matching,
+    metrics::METRICS,
+    modlog::ModerationEntry,
+    openai, permissions, privacy,
+    reports::ReportedMessage,
+    retry::{self, Outcome},
+    rule_commands,
+    scoring::Score,
+    settings::Setting,
+    state::State,
+};
 
-pub async fn handle_updates(bot: Bot, update: Update, config: &Config) -> Result<()> {
+pub async fn handle_updates(
+    bot: Bot,
+    update: Update,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
     match &update.kind {
         UpdateKind::Message(message) => {
-            handle_messages(&bot, &message, config).await?;
+            // Remember the message to clean up after the user if they are punished later
+            if let Some(user) = &message.from {
+                state.history.record(
+                    message.chat.id.0,
+                    user.id.0,
+                    message.id,
+                    message.date.timestamp(),
+                    config.history.capacity,
+                );
+            }
+
+            // Edits are not counted towards the flood limits
+            if handle_flood(&bot, message, config, state).await? {
+                return Ok(());
+            }
+            handle_messages(&bot, message, config, state).await?;
         }
         UpdateKind::EditedMessage(message) => {
-            handle_messages(&bot, &message, config).await?;
+            handle_messages(&bot, message, config, state).await?;
+        }
+        UpdateKind::ChatMember(member_updated) => {
+            // Keep the cached admins up to date on promotions and demotions
+            state
+                .admins
+                .update_member(member_updated.chat.id, &member_updated.new_chat_member);
+        }
+        UpdateKind::CallbackQuery(query) => {
+            handle_callback_query(&bot, query, config, state).await?;
+        }
+        UpdateKind::MyChatMember(member_updated) => {
+            handle_my_chat_member(&bot, member_updated, config).await;
         }
         _ => {} // Ignore other update types
     }
     Ok(())
 }
 
-async fn handle_messages(bot: &Bot, message: &Message, config: &Config) -> Result<()> {
+async fn handle_messages(
+    bot: &Bot,
+    message: &Message,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
     // Get group chat title
     let chat_title = if let Some(title) = &message.chat.title() {
         title
@@ -32,129 +91,693 @@ async fn handle_messages(bot: &Bot, message: &Message, config: &Config) -> Resul
     // Handle new chat members
     if let MessageKind::NewChatMembers(message_new_chat_members) = &message.kind {
         handle_message_new_chat_members(
-            &bot,
+            bot,
             message,
             chat_title,
             message_new_chat_members,
-            &config,
+            config,
+            state,
         )
         .await?;
+        return Ok(());
+    }
+
+    // Delete other service messages if configured
+    if handle_service_message(bot, message, chat_title, config, state).await? {
+        return Ok(());
+    }
+
+    let Some(user) = &message.from
+    else {
+        return Ok(());
+    };
+
+    // Ban users on the federated ban list right away
+    if handle_federated_ban(bot, message, user, Some(message.id), config, state).await? {
+        return Ok(());
+    }
+
+    // Handle spam reports by members, which are replies to the reported
+    // message. Anything else starting with `/report` is checked as usual.
+    if config.reports.enabled
+        && message.text().is_some_and(is_report_command)
+        && let Some(target) = message.reply_to_message()
+    {
+        return handle_report_command(bot, message, user, target, config, state).await;
     }
+
+    // Score the sender/forwarder names and the trustworthiness of the sender
+    let mut score = Score::default();
+    score.add_message_names(message, config)?;
+    score.add_user(user, config);
+
     // Handle common messages
-    else if let MessageKind::Common(message_common) = &message.kind {
-        if let Some(user) = &message.from {
-            let mut message_text: Option<&str> = None;
+    if let MessageKind::Common(message_common) = &message.kind {
+        // Collect the text, caption, quote, link previews and external reply of the message
+        let document = MessageDocument::from_message(message);
 
-            // Get message text from different media kinds
-            if let MediaKind::Text(media_text) = &message_common.media_kind {
-                message_text = Some(&media_text.text);
-            }
-            else if let Some(caption) = &message.caption() {
-                message_text = Some(&caption);
-            }
-            else if let MediaKind::Sticker(_) = &message_common.media_kind {
+        if document.is_empty() {
+            if let MediaKind::Sticker(_) = &message_common.media_kind {
                 debug!("Sticker message ignored");
             }
             else {
                 warn!("Unsupported media kind: {:?}", &message_common.media_kind);
             }
+        }
 
-            // Handle the message/caption
-            if let Some(message_text) = message_text {
-                // Process the original message text
-                handle_message_common_text(&bot, message, user, chat_title, message_text, &config)
-                    .await?;
+        // Respond to admin commands without checking the commands themselves.
+        // Commands sent by other users are checked like any other message.
+        if let Some(command) = message
+            .text()
+            .or(message.caption())
+            .and_then(AdminCommand::parse)
+            && is_admin(bot, message, user, command.name(), state).await?
+        {
+            return handle_admin_command(bot, message, user, command, config, state).await;
+        }
 
-                // Process URLs from TextLink entities (regular URLs are already in message text)
-                if let Some(entities) = message.entities() {
-                    for entity in entities {
-                        if let MessageEntityKind::TextLink {
-                            url,
-                        } = &entity.kind
-                        {
-                            handle_message_common_text(
-                                &bot,
-                                message,
-                                user,
-                                chat_title,
-                                url.as_str(),
-                                &config,
-                            )
-                            .await?;
-                        }
-                    }
-                }
-            }
+        // Handle the message document
+        if !document.is_empty() {
+            handle_message_document(bot, message, user, &document, &mut score, config, state)
+                .await?;
         }
     }
 
-    // Handle sender/forwarder names
-    if let Some(user) = &message.from {
-        handle_message_user_names(&bot, message, chat_title, user, config).await?;
+    let action = state.settings.get(message.chat.id).limit_action(
+        config
+            .thresholds_for(message.chat.id)
+            .action_for(score.total()),
+    );
+    log_score(&score, user, chat_title, message, action);
+    let reason = score.reason();
+    let outcome = actions::enforce(bot, state, message, user, action, &reason, config).await?;
+    record_action(
+        state,
+        message,
+        user,
+        &action.to_string(),
+        &reason,
+        Some(&score),
+        &outcome,
+    );
+    if outcome.taken {
+        // Clean up after muted and banned users
+        if action >= Action::Mute {
+            let window = config.history.cleanup_window;
+            purge_user_messages(bot, message.chat.id, user.id, window, state).await;
+        }
+        if action == Action::Ban {
+            let reason = format!("spam score {}", score.total());
+            federate_ban(bot, message.chat.id, user.id, &reason, config, state).await;
+        }
     }
-
     Ok(())
 }
 
-async fn handle_message_user_names(
+/// Records the message for flood detection and enforces the flood action if a
+/// limit was exceeded, returning whether it was
+async fn handle_flood(
+    bot: &Bot,
+    message: &Message,
+    config: &Config,
+    state: &State,
+) -> Result<bool> {
+    let MessageKind::Common(_) = &message.kind
+    else {
+        return Ok(false);
+    };
+    let Some(user) = &message.from
+    else {
+        return Ok(false);
+    };
+
+    let flood = config.flood_for(message.chat.id);
+    let Some(violation) = state.flood.record(
+        message.chat.id.0,
+        user.id.0,
+        FloodEvent::from_message(message),
+        flood,
+    )
+    else {
+        return Ok(false);
+    };
+
+    let chat_title = message.chat.title().unwrap_or("None");
+    warn!(
+        chat_id = message.chat.id.0,
+        user_id = user.id.0,
+        action = "flood",
+        "User '{}' ({}) sent {} {} within {} seconds in '{}' ({}), exceeding the limit of {}",
+        privacy::redact(&user.full_name()),
+        user.id,
+        violation.count,
+        violation.kind,
+        flood.window,
+        chat_title,
+        &message.chat.id,
+        violation.limit
+    );
+    let outcome =
+        actions::enforce_flood(bot, state, message, user, chat_title, &violation, config).await?;
+    record_action(
+        state,
+        message,
+        user,
+        "flood",
+        violation.kind,
+        None,
+        &outcome,
+    );
+    Ok(true)
+}
+
+/// An `/aufseher` command that only admins may use, with its trimmed arguments
+#[derive(Debug, PartialEq, Eq)]
+enum AdminCommand<'a> {
+    Explain(&'a str),
+    Purge(&'a str),
+    Pardon(&'a str),
+    Rule(&'a str),
+    Settings,
+    Status,
+}
+
+impl<'a> AdminCommand<'a> {
+    fn parse(message_text: &'a str) -> Option<AdminCommand<'a>> {
+        let text = message_text.strip_prefix("/aufseher ")?.trim_start();
+        let (name, arguments) = match text.split_once(char::is_whitespace) {
+            Some((name, arguments)) => (name, arguments.trim()),
+            None => (text, ""),
+        };
+        match name {
+            "explain" => Some(AdminCommand::Explain(arguments)),
+            "purge" => Some(AdminCommand::Purge(arguments)),
+            "pardon" => Some(AdminCommand::Pardon(arguments)),
+            "rule" => Some(AdminCommand::Rule(arguments)),
+            "settings" => Some(AdminCommand::Settings),
+            "status" => Some(AdminCommand::Status),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            AdminCommand::Explain(_) => "explain",
+            AdminCommand::Purge(_) => "purge",
+            AdminCommand::Pardon(_) => "pardon",
+            AdminCommand::Rule(_) => "rule",
+            AdminCommand::Settings => "settings",
+            AdminCommand::Status => "status",
+        }
+    }
+}
+
+/// Handles a command sent by an admin
+async fn handle_admin_command(
     bot: &Bot,
     message: &Message,
-    chat_title: &str,
     user: &User,
+    command: AdminCommand<'_>,
     config: &Config,
+    state: &State,
 ) -> Result<()> {
-    // Check if the username matches any of the regexes
-    if let Some(matched_regex) = matching::is_match(&user.full_name(), &config.name_regexes)? {
+    match command {
+        AdminCommand::Explain(arguments) => {
+            handle_explain_command(bot, message, arguments, config, state).await
+        }
+        AdminCommand::Purge(arguments) => {
+            handle_purge_command(bot, message, user, arguments, config, state).await
+        }
+        AdminCommand::Pardon(arguments) => {
+            handle_pardon_command(bot, message, user, arguments, config, state).await
+        }
+        AdminCommand::Rule(arguments) => {
+            handle_rule_command(bot, message, user, arguments, config).await
+        }
+        AdminCommand::Settings => handle_settings_command(bot, message, config, state).await,
+        AdminCommand::Status => handle_status_command(bot, message).await,
+    }
+}
+
+/// Returns whether the text is a `/report` command, which anyone may use
+fn is_report_command(message_text: &str) -> bool {
+    message_text == "/report"
+        || message_text.starts_with("/report ")
+        || message_text.starts_with("/report@")
+}
+
+/// Notifies the admins of a message reported by a member, adds it to the
+/// candidate spam corpus and deletes it once enough trusted members reported it
+async fn handle_report_command(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    target: &Message,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    // Members who took part in the chat and admins are trusted
+    let since = Utc::now().timestamp() - config.reports.trusted_window as i64;
+    let trusted = state
+        .history
+        .count_since(message.chat.id.0, user.id.0, since)
+        >= config.reports.trusted_min_messages
+        || state.admins.is_admin(bot, message.chat.id, user.id).await?;
+    let count = state.reports.record(
+        message.chat.id.0,
+        target.id.0,
+        user.id.0,
+        trusted,
+        Utc::now().timestamp(),
+    );
+
+    if let Some(count) = &count {
         info!(
-            "Username '{}' maches regex '{}'",
-            user.full_name(),
-            matched_regex.as_str()
+            chat_id = message.chat.id.0,
+            "User '{}' ({}) reported message {} ({} reports, {} by trusted members)",
+            privacy::redact(&user.full_name()),
+            user.id,
+            target.id,
+            count.reporters,
+            count.trusted_reporters
         );
-        actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
-    }
 
-    // Check if the message's forwarder username matches any of the regexes
-    if let Some(forwarder) = &message.forward_from_user() {
-        if let Some(matched_regex) =
-            matching::is_match(&forwarder.full_name(), &config.name_regexes)?
+        if count.first {
+            let text = privacy::persisted(&MessageDocument::from_message(target).combined_text());
+            state.candidates.record(&ReportedMessage {
+                time: Utc::now().timestamp(),
+                chat_id: message.chat.id.0,
+                message_id: target.id.0,
+                user_id: target.from.as_ref().map(|author| author.id.0),
+                text,
+                reporter_id: user.id.0,
+            });
+            notify_report(bot, message, user, target, config).await;
+        }
+
+        if config.reports.delete_threshold == Some(count.trusted_reporters)
+            && let Some(author) = &target.from
         {
-            info!(
-                "Forwarder name '{}' maches regex '{}'",
-                forwarder.full_name(),
-                matched_regex.as_str()
-            );
-            actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
+            let reason = format!("reported by {} trusted members", count.trusted_reporters);
+            let outcome =
+                actions::enforce(bot, state, target, author, Action::Delete, &reason, config)
+                    .await?;
+            record_action(state, target, author, "delete", &reason, None, &outcome);
         }
     }
 
-    // Check if the message's forwarder chat name matches any of the regexes
-    if let Some(forwarder) = &message.forward_from_chat() {
-        if let Some(title) = &forwarder.title() {
-            if let Some(matched_regex) = matching::is_match(&title, &config.name_regexes)? {
-                info!(
-                    "Forwarder chat name '{}' maches regex '{}'",
-                    title,
-                    matched_regex.as_str()
-                );
-                actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
-            }
+    // Keep the chat clean by removing the report itself
+    if let Err(error) =
+        retry::retry(|| bot.delete_message(message.chat.id, message.id).send()).await
+    {
+        METRICS.record_request_error(&error);
+        warn!("Failed to delete report message {}: {}", message.id, error);
+    }
+    Ok(())
+}
+
+/// Forwards a reported message to the log channel together with who reported it
+async fn notify_report(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    target: &Message,
+    config: &Config,
+) {
+    let Some(log_channel) = config.log_channel
+    else {
+        debug!(
+            "No log channel configured, report of message {} not forwarded",
+            target.id
+        );
+        return;
+    };
+
+    let author = match &target.from {
+        Some(author) => format!("{} ({})", author.full_name(), author.id),
+        None => "unknown".to_string(),
+    };
+    let mut text = format!(
+        "{} ({}) reported a message by {} in {}",
+        user.full_name(),
+        user.id,
+        author,
+        message.chat.title().unwrap_or("None")
+    );
+    if let Some(url) = target.url() {
+        text.push_str(&format!(": {}", url));
+    }
+
+    let log_channel = ChatId(log_channel);
+    let result = async {
+        retry::retry(|| {
+            bot.forward_message(log_channel, target.chat.id, target.id)
+                .send()
+        })
+        .await?;
+        retry::retry(|| bot.send_message(log_channel, text.clone()).send()).await
+    }
+    .await;
+    if let Err(error) = result {
+        METRICS.record_request_error(&error);
+        warn!(
+            "Failed to forward report of message {} to the log channel: {}",
+            target.id, error
+        );
+    }
+}
+
+async fn handle_explain_command(
+    bot: &Bot,
+    message: &Message,
+    arguments: &str,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    // Only admins and owners may inspect how the rules apply
+    let report = if let Some(target) = message.reply_to_message() {
+        explain::explain_message(bot, state, target, config).await?
+    }
+    else if !arguments.is_empty() {
+        explain::explain_text(arguments, message.chat.id, state, config).await?
+    }
+    else {
+        "Reply to a message or pass the text to explain.".to_string()
+    };
+
+    actions::send_command_response(bot, message, &report).await
+}
+
+/// Manages the rules added with admin commands, which apply to all chats
+async fn handle_rule_command(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    arguments: &str,
+    config: &Config,
+) -> Result<()> {
+    if !config.rule_admin_chats.contains(&message.chat.id.0) {
+        debug!(
+            chat_id = message.chat.id.0,
+            "Rule commands are not enabled in {}", message.chat.id
+        );
+        return Ok(());
+    }
+    let report = rule_commands::run_rule_command(arguments, message, user, config);
+    actions::send_command_response(bot, message, &report).await
+}
+
+/// Shows the settings menu of the chat
+async fn handle_settings_command(
+    bot: &Bot,
+    message: &Message,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    let settings = state.settings.get(message.chat.id);
+    bot.send_message(message.chat.id, "Settings for this chat:")
+        .reply_parameters(ReplyParameters::new(message.id))
+        .reply_markup(settings.keyboard(message.chat.id, config))
+        .disable_notification(true)
+        .await?;
+    Ok(())
+}
+
+/// Changes a setting when an admin presses a button of the settings menu
+async fn handle_callback_query(
+    bot: &Bot,
+    query: &CallbackQuery,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    let Some(setting) = query.data.as_deref().and_then(Setting::from_callback_data)
+    else {
+        return Ok(());
+    };
+    let Some(menu) = &query.message
+    else {
+        return Ok(());
+    };
+
+    let chat_id = menu.chat().id;
+    if !state.admins.is_admin(bot, chat_id, query.from.id).await? {
+        bot.answer_callback_query(query.id.clone())
+            .text("Only admins can change the settings.")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    let settings = state.toggle_setting(chat_id, setting, config)?;
+    info!(
+        chat_id = chat_id.0,
+        user_id = query.from.id.0,
+        "Setting '{}' has been changed by '{}' ({}) in {}: {:?}",
+        setting.key(),
+        privacy::redact(&query.from.full_name()),
+        query.from.id,
+        chat_id,
+        settings
+    );
+    bot.edit_message_reply_markup(chat_id, menu.id())
+        .reply_markup(settings.keyboard(chat_id, config))
+        .await?;
+    bot.answer_callback_query(query.id.clone()).await?;
+    Ok(())
+}
+
+/// Reports the version of the bot and whether it has the rights it needs in the chat
+async fn handle_status_command(bot: &Bot, message: &Message) -> Result<()> {
+    let me = retry::retry(|| bot.get_me().send()).await?;
+    let missing = permissions::check_chat(bot, me.id, message.chat.id).await?;
+    let report = format!(
+        "Aufseher {}\n{}",
+        crate::VERSION,
+        permissions::describe_rights(&missing)
+    );
+    actions::send_command_response(bot, message, &report).await
+}
+
+/// Checks the rights of the bot when it is added to a chat or its rights
+/// change, warning the admins about missing rights
+async fn handle_my_chat_member(bot: &Bot, member_updated: &ChatMemberUpdated, config: &Config) {
+    let chat = &member_updated.chat;
+    if chat.is_private() {
+        return;
+    }
+
+    let chat_title = chat.title().unwrap_or("None");
+    let member = &member_updated.new_chat_member;
+    if !member.is_present() {
+        info!(
+            chat_id = chat.id.0,
+            "The bot has been removed from '{}' ({})", chat_title, chat.id
+        );
+        return;
+    }
+
+    let missing = permissions::missing_rights(&member.kind);
+    if missing == permissions::missing_rights(&member_updated.old_chat_member.kind)
+        && member_updated.old_chat_member.is_present()
+    {
+        return;
+    }
+    if missing.is_empty() {
+        info!(
+            chat_id = chat.id.0,
+            "All required rights are granted in '{}' ({})", chat_title, chat.id
+        );
+    }
+    else {
+        permissions::warn_missing_rights(bot, chat.id, &missing, config).await;
+    }
+}
+
+async fn handle_purge_command(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    arguments: &str,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    let Some(target) = message
+        .reply_to_message()
+        .and_then(|target| target.from.as_ref())
+    else {
+        return actions::send_command_response(
+            bot,
+            message,
+            "Reply to a message of the user whose messages to delete.",
+        )
+        .await;
+    };
+
+    // The window can be given in minutes and defaults to the cleanup window
+    let window = if arguments.is_empty() {
+        config.history.cleanup_window
+    }
+    else if let Ok(minutes) = arguments.parse::<u64>() {
+        minutes * 60
+    }
+    else {
+        return actions::send_command_response(
+            bot,
+            message,
+            "Usage: /aufseher purge [minutes], as a reply to a message of the user.",
+        )
+        .await;
+    };
+
+    info!(
+        "User '{}' ({}) requested to delete the messages of '{}' ({}) from the last {} seconds",
+        privacy::redact(&user.full_name()),
+        user.id,
+        privacy::redact(&target.full_name()),
+        target.id,
+        window
+    );
+    for chat_id in [message.chat.id]
+        .into_iter()
+        .chain(config.federated_chats_except(message.chat.id))
+    {
+        purge_user_messages(bot, chat_id, target.id, window, state).await;
+    }
+    retry::retry(|| bot.delete_message(message.chat.id, message.id).send()).await?;
+
+    Ok(())
+}
+
+/// Lifts a ban that was a false positive and records the pardon in the
+/// moderation log, which `aufseher export` uses to label the corpus
+async fn handle_pardon_command(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    arguments: &str,
+    config: &Config,
+    state: &State,
+) -> Result<()> {
+    let Ok(user_id) = arguments.parse::<u64>()
+    else {
+        return actions::send_command_response(bot, message, "Usage: /aufseher pardon <user ID>")
+            .await;
+    };
+    let user_id = UserId(user_id);
+
+    let mut outcome = Outcome::default();
+    outcome.taken = outcome
+        .step("unban", || {
+            bot.unban_chat_member(message.chat.id, user_id)
+                .only_if_banned(true)
+                .send()
+        })
+        .await
+        .is_some();
+    // Lift the bans that were propagated to the other federated chats as well
+    for chat_id in config.federated_chats_except(message.chat.id) {
+        outcome
+            .step("federated unban", || {
+                bot.unban_chat_member(chat_id, user_id)
+                    .only_if_banned(true)
+                    .send()
+            })
+            .await;
+    }
+    let federated = match state.remove_federated_ban(user_id.0, config) {
+        Ok(removed) => removed,
+        Err(error) => {
+            outcome.fail("federated ban list", &error);
+            false
         }
+    };
+
+    info!(
+        chat_id = message.chat.id.0,
+        "User '{}' ({}) pardoned user {}",
+        privacy::redact(&user.full_name()),
+        user.id,
+        user_id
+    );
+    // The admin is identified by ID only, as their name is not needed to export pardons
+    let reason = format!("pardoned by admin {}", user.id);
+    state.moderation_log.record(&ModerationEntry::new(
+        message.chat.id.0,
+        user_id.0,
+        "pardon",
+        &reason,
+        &outcome,
+    ));
+
+    let response = if !outcome.taken {
+        format!("Failed to unban user {}.", user_id)
     }
+    else if federated {
+        format!(
+            "User {} has been unbanned and removed from the federated ban list.",
+            user_id
+        )
+    }
+    else {
+        format!("User {} has been unbanned.", user_id)
+    };
+    actions::send_command_response(bot, message, &response).await
+}
 
-    // Check if the message's via_bot username matches any of the regexes
-    if let Some(via_bot) = &message.via_bot {
-        if let Some(matched_regex) = matching::is_match(&via_bot.full_name(), &config.name_regexes)?
-        {
-            info!(
-                "Via bot name '{}' maches regex '{}'",
-                via_bot.full_name(),
-                matched_regex.as_str()
+/// Returns whether the sender of a command is an admin or owner, who are the
+/// only ones that may use commands
+async fn is_admin(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    command: &str,
+    state: &State,
+) -> Result<bool> {
+    if !state.admins.is_admin(bot, message.chat.id, user.id).await? {
+        warn!(
+            "User '{}' ({}) is not an admin or creator. Checking the {} command like any message.",
+            privacy::redact(&user.full_name()),
+            user.id,
+            command
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Deletes the recorded messages a user sent in a chat within the window
+async fn purge_user_messages(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    window: u64,
+    state: &State,
+) {
+    if window == 0 {
+        return;
+    }
+
+    let since = Utc::now().timestamp() - window as i64;
+    let message_ids = state.history.take_since(chat_id.0, user_id.0, since);
+    if message_ids.is_empty() {
+        return;
+    }
+
+    match actions::delete_messages(bot, chat_id, &message_ids).await {
+        Ok(()) => warn!(
+            "{} recent messages from user {} have been deleted from {}",
+            message_ids.len(),
+            user_id,
+            chat_id
+        ),
+        Err(error) => {
+            METRICS.record_error(&error);
+            warn!(
+                "Failed to delete recent messages from user {} in {}: {}",
+                user_id, chat_id, error
             );
-            actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
         }
     }
-
-    Ok(())
 }
 
 async fn handle_message_new_chat_members(
@@ -163,91 +786,595 @@ async fn handle_message_new_chat_members(
     chat_title: &str,
     message_new_chat_members: &MessageNewChatMembers,
     config: &Config,
+    state: &State,
 ) -> Result<()> {
+    // Actions against a member delete the join message along with it
+    let mut join_message_deleted = false;
+    let mut member_banned = false;
+
     for member in &message_new_chat_members.new_chat_members {
         info!(
+            chat_id = message.chat.id.0,
+            user_id = member.id.0,
             "New member '{}' ({}) joined '{}' ({})",
-            member.full_name(),
+            privacy::redact(&member.full_name()),
             member.id,
             chat_title,
             &message.chat.id
         );
 
-        // Check if the username matches any of the regexes
-        if let Some(matched_regex) = matching::is_match(&member.full_name(), &config.name_regexes)?
-        {
-            info!(
-                "Username '{}' maches regex '{}'",
-                member.full_name(),
-                matched_regex.as_str()
-            );
-            actions::delete_messages_and_ban_user(bot, message, member, chat_title).await?;
+        // Ban members on the federated ban list right away
+        if handle_federated_ban(bot, message, member, None, config, state).await? {
+            member_banned = true;
+            continue;
+        }
+
+        // Score the name and the trustworthiness of the new member
+        let mut score = Score::default();
+        score.add_name("member name", &member.full_name(), config)?;
+        score.add_user(member, config);
+
+        let action = state.settings.get(message.chat.id).limit_action(
+            config
+                .thresholds_for(message.chat.id)
+                .action_for(score.total()),
+        );
+        log_score(&score, member, chat_title, message, action);
+        let reason = score.reason();
+        let outcome =
+            actions::enforce(bot, state, message, member, action, &reason, config).await?;
+        record_action(
+            state,
+            message,
+            member,
+            &action.to_string(),
+            &reason,
+            Some(&score),
+            &outcome,
+        );
+        if outcome.taken {
+            join_message_deleted = true;
+            if action == Action::Ban {
+                let reason = format!("member spam score {}", score.total());
+                federate_ban(bot, message.chat.id, member.id, &reason, config, state).await;
+            }
         }
     }
 
+    // Delete the join message if configured or if a member was banned when joining
+    if !join_message_deleted
+        && (member_banned
+            || state
+                .settings
+                .get(message.chat.id)
+                .service_messages(message.chat.id, config)
+                .joins)
+    {
+        retry::retry(|| bot.delete_message(message.chat.id, message.id).send()).await?;
+        info!(
+            "Join message {} has been deleted from '{}' ({})",
+            message.id, chat_title, &message.chat.id
+        );
+    }
+
     Ok(())
 }
 
-async fn handle_message_common_text(
+/// Deletes the message if it is a service message that is configured to be
+/// deleted, returning whether it was deleted
+async fn handle_service_message(
     bot: &Bot,
     message: &Message,
-    user: &User,
     chat_title: &str,
-    message_text: &str,
     config: &Config,
+    state: &State,
+) -> Result<bool> {
+    let service_messages = state
+        .settings
+        .get(message.chat.id)
+        .service_messages(message.chat.id, config);
+    let (kind, delete) = match &message.kind {
+        MessageKind::LeftChatMember(_) => ("leave", service_messages.leaves),
+        MessageKind::Pinned(_) => ("pinned", service_messages.pinned),
+        MessageKind::NewChatTitle(_) => ("title change", service_messages.title_changes),
+        _ => return Ok(false),
+    };
+    if !delete {
+        return Ok(false);
+    }
+
+    retry::retry(|| bot.delete_message(message.chat.id, message.id).send()).await?;
+    info!(
+        "The {} message {} has been deleted from '{}' ({})",
+        kind, message.id, chat_title, &message.chat.id
+    );
+    Ok(true)
+}
+
+async fn handle_message_document(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    document: &MessageDocument,
+    score: &mut Score,
+    config: &Config,
+    state: &State,
 ) -> Result<()> {
+    let combined_text = document.combined_text();
+    let chat_title = message.chat.title().unwrap_or("None");
     info!(
+        chat_id = message.chat.id.0,
+        user_id = user.id.0,
         "New message '{}' from '{}' ({}) in '{}' ({})",
-        message_text,
-        user.full_name(),
+        privacy::redact(&combined_text),
+        privacy::redact(&user.full_name()),
         user.id,
         chat_title,
         &message.chat.id
     );
 
-    // Check if the message text matches any of the regexes
-    if let Some(matched_regex) = matching::is_match(&message_text, &config.message_regexes)? {
-        info!(
-            "Message text '{}' maches regex '{}'",
-            message_text,
-            matched_regex.as_str()
-        );
-        actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
-    }
-    // Then check if the deobfuscated message text matches any of the regexes
-    else if let Some(matched_regex) =
-        matching::is_match_obfuscated(&message_text, &config.message_regexes)?
-    {
-        info!(
-            "Deobfuscated message text of '{}' maches regex '{}'",
-            message_text,
-            matched_regex.as_str()
-        );
-        actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
-    }
+    // Score every field on its own, then all fields combined so rules can match across
+    // fields, counting quoted content only as far as configured
+    score.add_document(document, config)?;
+
+    // Check whether the same content is posted by other users or in other chats
+    handle_duplicates(bot, message, user, &combined_text, score, config, state).await;
 
     // Respond to `/aufseher ping` command
-    if message_text == "/aufseher ping" {
+    if message.text() == Some("/aufseher ping") {
         actions::send_ping_response(bot, message).await?;
     }
 
-    // If the OpenAI API key is provided, use GPT-4o to check if the message is spam
-    if let Some(openai_api_key) = &config.openai_api_key {
+    // If the OpenAI API key is provided, use GPT-4o to check if the sender's own
+    // text is spam, unless the message already scored high enough to be banned
+    let own_text = document.own_text();
+    let thresholds = config.thresholds_for(message.chat.id);
+    if let Some(openai_api_key) = &config.openai_api_key
+        && !own_text.is_empty()
+        && state.settings.get(message.chat.id).llm_enabled()
+        && thresholds.action_for(score.total()) != Action::Ban
+    {
         info!("Checking if message is spam using GPT-4o");
         let openai_is_spam =
-            openai::openai_check_is_message_spam(&message_text, openai_api_key).await?;
+            openai::openai_check_is_message_spam(&own_text, openai_api_key).await?;
 
         if openai_is_spam {
-            info!("Message '{}' is recognized as spam by GPT-4o", message_text);
-            actions::delete_messages_and_ban_user(bot, message, user, chat_title).await?;
+            info!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                "Message '{}' is recognized as spam by GPT-4o",
+                privacy::redact(&own_text)
+            );
+            score.add_signal("llm", config.signals.llm);
         }
         else {
             info!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
                 "Message '{}' is recognized as NOT spam by GPT-4o",
-                message_text
+                privacy::redact(&own_text)
             );
         }
     }
 
     Ok(())
 }
+
+async fn handle_duplicates(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    combined_text: &str,
+    score: &mut Score,
+    config: &Config,
+    state: &State,
+) {
+    let duplicates = &config.duplicates;
+    if combined_text.chars().count() < duplicates.min_length {
+        return;
+    }
+
+    let deobfuscated_text =
+        matching::deobfuscate_message_text(combined_text, &config.deobfuscation);
+    let posting = Posting {
+        time: message.date.timestamp(),
+        fingerprint: Fingerprint::new(&deobfuscated_text),
+        chat_id: message.chat.id.0,
+        user_id: user.id.0,
+        message_id: message.id.0,
+    };
+    let verdict = state.fingerprints.record(posting, duplicates);
+    let other_evidence = add_duplicate_signal(
+        score,
+        &verdict,
+        config.thresholds_for(message.chat.id),
+        duplicates.weight,
+    );
+    match verdict {
+        DuplicateVerdict::None | DuplicateVerdict::Blocked => {}
+        DuplicateVerdict::Escalated(earlier) => {
+            warn!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                "Message '{}' was posted {} times within {} seconds, blocking the content for {} seconds",
+                privacy::redact(combined_text),
+                earlier.len() + 1,
+                duplicates.window,
+                duplicates.block_duration
+            );
+            // Ban everyone who posted the content before in the chats they posted it in
+            if duplicates.ban_everywhere && other_evidence {
+                for posting in earlier {
+                    let chat_id = ChatId(posting.chat_id);
+                    let user_id = UserId(posting.user_id);
+                    let action = state.settings.get(chat_id).limit_action(Action::Ban);
+                    match actions::enforce_in_chat(
+                        bot,
+                        state,
+                        chat_id,
+                        user_id,
+                        Some(MessageId(posting.message_id)),
+                        action,
+                        config,
+                    )
+                    .await
+                    {
+                        Ok(outcome) => {
+                            record_action_in_chat(
+                                state,
+                                chat_id,
+                                user_id,
+                                action,
+                                "duplicate content",
+                                &outcome,
+                            );
+                            if outcome.taken && action >= Action::Mute {
+                                let window = config.history.cleanup_window;
+                                purge_user_messages(bot, chat_id, user_id, window, state).await;
+                            }
+                            if outcome.taken && action == Action::Ban {
+                                federate_ban(
+                                    bot,
+                                    chat_id,
+                                    user_id,
+                                    "duplicate content",
+                                    config,
+                                    state,
+                                )
+                                .await;
+                            }
+                        }
+                        Err(error) => {
+                            METRICS.record_error(&error);
+                            warn!(
+                                "Failed to ban user {} in chat {}: {}",
+                                user_id, chat_id, error
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adds the duplicate signal to the score if the other signals already found
+/// the message to be spam, returning whether they did
+///
+/// Repeated content alone is no proof of spam, so it only makes the action
+/// against a message more severe and never punishes a message on its own.
+fn add_duplicate_signal(
+    score: &mut Score,
+    verdict: &DuplicateVerdict,
+    thresholds: &Thresholds,
+    weight: f64,
+) -> bool {
+    let source = match verdict {
+        DuplicateVerdict::None => return false,
+        DuplicateVerdict::Blocked => "blocked duplicate content",
+        DuplicateVerdict::Escalated(_) => "duplicate content",
+    };
+    if thresholds.action_for(score.total()) < Action::Mute {
+        return false;
+    }
+
+    score.add_signal(source, weight);
+    true
+}
+
+/// Bans the user if they are on the federated ban list and the chat is
+/// federated, returning whether an action was taken against them (a milder
+/// one if the chat does not allow bans)
+async fn handle_federated_ban(
+    bot: &Bot,
+    message: &Message,
+    user: &User,
+    message_id: Option<MessageId>,
+    config: &Config,
+    state: &State,
+) -> Result<bool> {
+    if !config.is_federated(message.chat.id) {
+        return Ok(false);
+    }
+    let Some(ban) = state.bans.get(user.id.0)
+    else {
+        return Ok(false);
+    };
+
+    info!(
+        chat_id = message.chat.id.0,
+        user_id = user.id.0,
+        "User '{}' ({}) is on the federated ban list (banned in {} for {})",
+        privacy::redact(&user.full_name()),
+        user.id,
+        ban.chat_id,
+        ban.reason
+    );
+    let action = state
+        .settings
+        .get(message.chat.id)
+        .limit_action(Action::Ban);
+    let outcome = actions::enforce_in_chat(
+        bot,
+        state,
+        message.chat.id,
+        user.id,
+        message_id,
+        action,
+        config,
+    )
+    .await?;
+    record_action(
+        state,
+        message,
+        user,
+        &action.to_string(),
+        "federated ban",
+        None,
+        &outcome,
+    );
+    Ok(outcome.taken)
+}
+
+/// Adds a user banned in a federated chat to the ban list and bans them in all
+/// other federated chats
+async fn federate_ban(
+    bot: &Bot,
+    chat_id: ChatId,
+    user_id: UserId,
+    reason: &str,
+    config: &Config,
+    state: &State,
+) {
+    if !config.is_federated(chat_id) {
+        return;
+    }
+
+    let ban = FederatedBan {
+        chat_id: chat_id.0,
+        time: Utc::now().timestamp(),
+        reason: reason.to_string(),
+    };
+    match state.add_federated_ban(user_id.0, ban, config) {
+        Ok(true) => {}
+        // Already banned everywhere
+        Ok(false) => return,
+        Err(error) => warn!("Failed to save the federated ban list: {}", error),
+    }
+
+    for other_chat_id in config.federated_chats_except(chat_id) {
+        let action = state.settings.get(other_chat_id).limit_action(Action::Ban);
+        match actions::enforce_in_chat(bot, state, other_chat_id, user_id, None, action, config)
+            .await
+        {
+            Ok(outcome) => {
+                record_action_in_chat(
+                    state,
+                    other_chat_id,
+                    user_id,
+                    action,
+                    "federated ban",
+                    &outcome,
+                );
+                if outcome.taken {
+                    let window = config.history.cleanup_window;
+                    purge_user_messages(bot, other_chat_id, user_id, window, state).await;
+                }
+            }
+            Err(error) => {
+                METRICS.record_error(&error);
+                warn!(
+                    "Failed to ban user {} in federated chat {}: {}",
+                    user_id, other_chat_id, error
+                );
+            }
+        }
+    }
+}
+
+/// Records an action taken because of a message in the moderation log,
+/// unless nothing was attempted (e.g., because the user is an admin)
+///
+/// The score is given if the action was taken because of it, so that the
+/// rules matching the name and text of the user are recorded for export.
+fn record_action(
+    state: &State,
+    message: &Message,
+    user: &User,
+    action: &str,
+    reason: &str,
+    score: Option<&Score>,
+    outcome: &Outcome,
+) {
+    if !outcome.taken && outcome.failures.is_empty() {
+        return;
+    }
+
+    let mut entry = ModerationEntry::new(message.chat.id.0, user.id.0, action, reason, outcome);
+    entry.message_id = Some(message.id.0);
+    entry.user_name = privacy::persisted(&user.full_name());
+    entry.text = message
+        .text()
+        .or(message.caption())
+        .and_then(privacy::persisted);
+    entry.rule_kinds = score.map(Score::own_rule_kinds).unwrap_or_default();
+    state.moderation_log.record(&entry);
+}
+
+/// Records an action in another chat than the one of the handled message in the moderation log
+fn record_action_in_chat(
+    state: &State,
+    chat_id: ChatId,
+    user_id: UserId,
+    action: Action,
+    reason: &str,
+    outcome: &Outcome,
+) {
+    if !outcome.taken && outcome.failures.is_empty() {
+        return;
+    }
+
+    let entry = ModerationEntry::new(chat_id.0, user_id.0, &action.to_string(), reason, outcome);
+    state.moderation_log.record(&entry);
+}
+
+fn log_score(score: &Score, user: &User, chat_title: &str, message: &Message, action: Action) {
+    METRICS.record_score(score);
+    if score.signals.is_empty() {
+        return;
+    }
+
+    for signal in &score.signals {
+        match &signal.rule_id {
+            Some(rule_id) => info!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                rule_id = rule_id.as_str(),
+                "The {} of '{}' ({}) matches rule '{}' (weight {})",
+                signal.source,
+                privacy::redact(&user.full_name()),
+                user.id,
+                rule_id,
+                signal.weight
+            ),
+            None => info!(
+                chat_id = message.chat.id.0,
+                user_id = user.id.0,
+                "Signal '{}' applies to '{}' ({}) (weight {})",
+                signal.source,
+                privacy::redact(&user.full_name()),
+                user.id,
+                signal.weight
+            ),
+        }
+    }
+    info!(
+        chat_id = message.chat.id.0, user_id = user.id.0, action = %action,
+        "User '{}' ({}) scored {} in '{}' ({}), action: {}",
+        privacy::redact(&user.full_name()),
+        user.id,
+        score.total(),
+        chat_title,
+        &message.chat.id,
+        action
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::Message;
+
+    use crate::{
+        actions::Action,
+        config::{Config, Thresholds},
+        document::MessageDocument,
+        duplicates::DuplicateVerdict,
+        handlers::{self, AdminCommand},
+        scoring::Score,
+    };
+
+    #[test]
+    fn test_admin_commands() {
+        assert_eq!(
+            AdminCommand::parse("/aufseher purge 10"),
+            Some(AdminCommand::Purge("10"))
+        );
+        assert_eq!(
+            AdminCommand::parse("/aufseher  status"),
+            Some(AdminCommand::Status)
+        );
+        assert_eq!(AdminCommand::parse("/aufseher statuses"), None);
+        assert_eq!(AdminCommand::parse("/aufseher ping"), None);
+
+        // The text of a command sent by a non-admin is scored like any other message
+        let config = Config::from_yaml_str("message_regexes: ['(?i)cheap followers']\n");
+        let message: Message = serde_json::from_str(
+            r#"{
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": -100, "type": "supergroup", "title": "Group"},
+                "from": {"id": 1, "is_bot": false, "first_name": "Mallory"},
+                "text": "/aufseher status Cheap followers at t.me/spam"
+            }"#,
+        )
+        .unwrap();
+        let command = AdminCommand::parse(message.text().unwrap());
+        assert_eq!(command, Some(AdminCommand::Status));
+
+        let mut score = Score::default();
+        score
+            .add_document(&MessageDocument::from_message(&message), &config)
+            .unwrap();
+        assert_eq!(
+            config
+                .thresholds_for(message.chat.id)
+                .action_for(score.total()),
+            Action::Ban
+        );
+    }
+
+    #[test]
+    fn test_duplicate_signal() {
+        let thresholds = Thresholds {
+            delete: Some(0.5),
+            mute: Some(0.8),
+            ban: Some(1.0),
+        };
+
+        // Duplicate content alone never punishes, however heavy the signal
+        for verdict in [
+            DuplicateVerdict::Blocked,
+            DuplicateVerdict::Escalated(Vec::new()),
+        ] {
+            let mut score = Score::default();
+            assert!(!handlers::add_duplicate_signal(
+                &mut score,
+                &verdict,
+                &thresholds,
+                10.0
+            ));
+            assert_eq!(thresholds.action_for(score.total()), Action::None);
+
+            // Weak evidence is not enough either
+            score.add_signal("no username", 0.5);
+            assert!(!handlers::add_duplicate_signal(
+                &mut score,
+                &verdict,
+                &thresholds,
+                10.0
+            ));
+            assert_eq!(thresholds.action_for(score.total()), Action::Delete);
+        }
+
+        // Duplicates make the action against spam found by other signals more severe
+        let mut score = Score::default();
+        score.add_signal("message rule", 0.8);
+        assert!(handlers::add_duplicate_signal(
+            &mut score,
+            &DuplicateVerdict::Blocked,
+            &thresholds,
+            0.5
+        ));
+        assert_eq!(thresholds.action_for(score.total()), Action::Ban);
+    }
+}
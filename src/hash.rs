@@ -0,0 +1,7 @@
+/// Hashes text with FNV-1a, which is stable across Rust versions, so
+/// persisted hashes stay valid
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
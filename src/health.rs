@@ -0,0 +1,318 @@
+use std::{
+    fs,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicI64, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use chrono::Utc;
+use futures_core::Stream;
+use teloxide::{
+    prelude::*,
+    stop::StopToken,
+    types::AllowedUpdate,
+    update_listeners::{AsUpdateStream, UpdateListener},
+};
+use tokio::time::{self, Duration};
+use tracing::warn;
+
+use crate::state::State;
+
+/// How long a `getUpdates` request waits for updates before returning none
+pub const POLLING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Polling is unhealthy after this many polling timeouts without a round trip
+const MAX_MISSED_POLLS: u32 = 12;
+
+/// How often the Telegram API is contacted when no updates arrive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Requests older than this many seconds no longer count towards health
+const MAX_SILENCE: i64 = 120;
+
+/// Liveness and readiness of the bot, reported by `/healthz` and `/readyz`
+///
+/// Polling is unhealthy if a `getUpdates` request failed recently and no
+/// update was received since. Failed requests are retried with a backoff of
+/// at most 64 seconds, so polling that keeps failing is reported until it
+/// recovers. Polling is also unhealthy if the listener made no round trip for
+/// [`MAX_MISSED_POLLS`] polling timeouts, which happens when it stopped or the
+/// dispatcher hangs. The Telegram API is additionally contacted with a
+/// heartbeat request, which only counts towards readiness.
+#[derive(Default)]
+pub struct Health {
+    /// Unix timestamp of when the health was created
+    started: i64,
+    /// Unix timestamp of the last round trip of the update listener
+    last_poll: AtomicI64,
+    /// Unix timestamp of the last update received by polling
+    last_update: AtomicI64,
+    /// Unix timestamp of the last failed `getUpdates` request
+    last_poll_error: AtomicI64,
+    /// Unix timestamp of the last successful heartbeat request
+    last_heartbeat: AtomicI64,
+    /// Unix timestamp of the last failed heartbeat request
+    last_heartbeat_error: AtomicI64,
+    config_loaded: AtomicBool,
+    data_dir: Option<PathBuf>,
+}
+
+impl Health {
+    pub fn new(data_dir: Option<PathBuf>) -> Health {
+        Health {
+            started: Utc::now().timestamp(),
+            data_dir,
+            ..Health::default()
+        }
+    }
+
+    pub fn record_update(&self) {
+        self.last_update
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn record_poll(&self) {
+        self.last_poll
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn record_poll_error(&self) {
+        self.last_poll_error
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn record_heartbeat(&self) {
+        self.last_heartbeat
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn record_heartbeat_error(&self) {
+        self.last_heartbeat_error
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn set_config_loaded(&self) {
+        self.config_loaded.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns why polling is unhealthy, if it is
+    pub fn check_polling(&self) -> Option<String> {
+        let last_update = self.last_update.load(Ordering::Relaxed);
+        let last_error = self.last_poll_error.load(Ordering::Relaxed);
+        let last_poll = self.last_poll.load(Ordering::Relaxed).max(self.started);
+        let now = Utc::now().timestamp();
+        let age = now - last_error;
+        let max_poll_silence = (POLLING_TIMEOUT * MAX_MISSED_POLLS).as_secs() as i64;
+
+        if last_poll != 0 && now - last_poll > max_poll_silence {
+            Some(format!(
+                "the update listener made no round trip for {} seconds",
+                now - last_poll
+            ))
+        }
+        else if last_error >= last_update && last_error != 0 && age <= MAX_SILENCE {
+            Some(format!(
+                "getting updates failed {} seconds ago and no update was received since",
+                age
+            ))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Returns why the Telegram API is unreachable according to the heartbeat, if it is
+    fn check_api(&self) -> Option<String> {
+        let last_heartbeat = self.last_heartbeat.load(Ordering::Relaxed);
+        let last_error = self.last_heartbeat_error.load(Ordering::Relaxed);
+        let silence = Utc::now().timestamp() - last_heartbeat;
+
+        if last_heartbeat == 0 {
+            Some("the Telegram API has not been reached yet".to_string())
+        }
+        else if last_error >= last_heartbeat {
+            Some("the last heartbeat request failed".to_string())
+        }
+        else if silence > MAX_SILENCE {
+            Some(format!(
+                "the Telegram API was last reached {} seconds ago",
+                silence
+            ))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Returns all reasons why the bot is not ready to moderate
+    pub fn check_readiness(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self.check_polling().into_iter().collect();
+        if let Some(problem) = self.check_api() {
+            problems.push(problem);
+        }
+        if !self.config_loaded.load(Ordering::Relaxed) {
+            problems.push("the config has not been loaded".to_string());
+        }
+        if let Some(problem) = self.check_storage() {
+            problems.push(problem);
+        }
+        problems
+    }
+
+    // The data directory is reachable if a file can be written to it
+    fn check_storage(&self) -> Option<String> {
+        let data_dir = self.data_dir.as_ref()?;
+        let probe = data_dir.join(".health");
+        match fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+            Ok(()) => None,
+            Err(error) => Some(format!(
+                "the data directory '{}' is not writable: {}",
+                data_dir.display(),
+                error
+            )),
+        }
+    }
+}
+
+/// Contacts the Telegram API periodically so that it is known to be reachable
+/// even when no updates arrive
+pub async fn run_heartbeat(bot: Bot, health: &Health) {
+    let mut interval = time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        match bot.get_me().await {
+            Ok(_) => health.record_heartbeat(),
+            Err(error) => {
+                health.record_heartbeat_error();
+                warn!("Telegram heartbeat failed: {}", error);
+            }
+        }
+    }
+}
+
+/// Update listener that records every round trip of the listener it wraps
+///
+/// The dispatcher polls the update stream whenever a `getUpdates` request
+/// returns, including long polls without updates, and whenever the backoff
+/// after a failed request ends.
+pub struct ObservedListener<L> {
+    listener: L,
+    state: Arc<State>,
+}
+
+impl<L> ObservedListener<L> {
+    pub fn new(listener: L, state: Arc<State>) -> ObservedListener<L> {
+        ObservedListener {
+            listener,
+            state,
+        }
+    }
+}
+
+impl<L> UpdateListener for ObservedListener<L>
+where
+    L: UpdateListener,
+{
+    type Err = L::Err;
+
+    fn stop_token(&mut self) -> StopToken {
+        self.listener.stop_token()
+    }
+
+    fn hint_allowed_updates(&mut self, hint: &mut dyn Iterator<Item = AllowedUpdate>) {
+        self.listener.hint_allowed_updates(hint)
+    }
+}
+
+impl<'a, L> AsUpdateStream<'a> for ObservedListener<L>
+where
+    L: AsUpdateStream<'a>,
+{
+    type StreamErr = L::StreamErr;
+    type Stream = ObservedStream<'a, L::Stream>;
+
+    fn as_stream(&'a mut self) -> Self::Stream {
+        ObservedStream {
+            stream: Box::pin(self.listener.as_stream()),
+            health: &self.state.health,
+        }
+    }
+}
+
+pub struct ObservedStream<'a, S> {
+    stream: Pin<Box<S>>,
+    health: &'a Health,
+}
+
+impl<S> Stream for ObservedStream<'_, S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        self.health.record_poll();
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use chrono::Utc;
+
+    use crate::health::{Health, MAX_MISSED_POLLS, MAX_SILENCE, POLLING_TIMEOUT};
+
+    #[test]
+    fn test_health() {
+        let health = Health::new(Some(std::env::temp_dir()));
+        assert!(health.check_polling().is_none());
+        assert!(!health.check_readiness().is_empty());
+
+        health.set_config_loaded();
+        health.record_heartbeat();
+        assert!(health.check_readiness().is_empty());
+
+        // A failed poll makes polling unhealthy until the next update, even
+        // if the heartbeat still reaches the API
+        health.record_poll_error();
+        health.record_heartbeat();
+        assert!(health.check_polling().is_some());
+        health
+            .last_poll_error
+            .store(Utc::now().timestamp() - 1, Ordering::Relaxed);
+        health.record_update();
+        assert!(health.check_polling().is_none());
+
+        // Not reaching the API for too long makes the bot unready
+        health
+            .last_heartbeat
+            .store(Utc::now().timestamp() - MAX_SILENCE - 1, Ordering::Relaxed);
+        assert!(!health.check_readiness().is_empty());
+    }
+
+    #[test]
+    fn test_stopped_listener() {
+        let health = Health::new(None);
+        health.record_poll();
+        assert!(health.check_polling().is_none());
+
+        // A listener that stops polling without errors makes polling unhealthy
+        let max_poll_silence = (POLLING_TIMEOUT * MAX_MISSED_POLLS).as_secs() as i64;
+        let started = Utc::now().timestamp() - max_poll_silence - 10;
+        let health = Health {
+            started,
+            ..Health::new(None)
+        };
+        assert!(health.check_polling().is_some());
+        health.last_poll.store(started + 5, Ordering::Relaxed);
+        assert!(health.check_polling().is_some());
+        health.record_poll();
+        assert!(health.check_polling().is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::MessageId;
+
+/// A message recorded so it can be deleted later
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct RecentMessage {
+    /// Unix timestamp of the message
+    time: i64,
+    message_id: i32,
+}
+
+/// The most recent messages of every user in every chat, kept so that the
+/// messages of a punished user can be cleaned up retroactively
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MessageHistory {
+    /// Messages by chat ID and user ID, oldest first
+    messages: Mutex<HashMap<i64, HashMap<u64, VecDeque<RecentMessage>>>>,
+}
+
+impl MessageHistory {
+    /// Records a message, dropping the oldest message of the user in the chat
+    /// if more than `capacity` messages are recorded
+    pub fn record(
+        &self,
+        chat_id: i64,
+        user_id: u64,
+        message_id: MessageId,
+        time: i64,
+        capacity: usize,
+    ) {
+        if capacity == 0 {
+            return;
+        }
+
+        let mut messages = self.messages.lock().unwrap();
+        let user_messages = messages
+            .entry(chat_id)
+            .or_default()
+            .entry(user_id)
+            .or_default();
+        if user_messages.len() >= capacity {
+            user_messages.pop_front();
+        }
+        user_messages.push_back(RecentMessage {
+            time,
+            message_id: message_id.0,
+        });
+    }
+
+    /// Returns the IDs of all chats with recorded messages
+    pub fn chat_ids(&self) -> Vec<i64> {
+        self.messages.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Returns the number of recorded messages a user sent in a chat since the given time
+    pub fn count_since(&self, chat_id: i64, user_id: u64, since: i64) -> usize {
+        let messages = self.messages.lock().unwrap();
+        messages
+            .get(&chat_id)
+            .and_then(|chat_messages| chat_messages.get(&user_id))
+            .map_or(0, |user_messages| {
+                user_messages
+                    .iter()
+                    .filter(|message| message.time >= since)
+                    .count()
+            })
+    }
+
+    /// Forgets the users whose last message in a chat is older than the given
+    /// time, and chats without any users left
+    pub fn prune(&self, before: i64) {
+        let mut messages = self.messages.lock().unwrap();
+        for chat_messages in messages.values_mut() {
+            chat_messages.retain(|_, user_messages| {
+                user_messages
+                    .back()
+                    .is_some_and(|message| message.time >= before)
+            });
+        }
+        messages.retain(|_, chat_messages| !chat_messages.is_empty());
+    }
+
+    /// Removes and returns the messages a user sent in a chat since the given time
+    pub fn take_since(&self, chat_id: i64, user_id: u64, since: i64) -> Vec<MessageId> {
+        let mut messages = self.messages.lock().unwrap();
+        let Some(chat_messages) = messages.get_mut(&chat_id)
+        else {
+            return Vec::new();
+        };
+        let Some(user_messages) = chat_messages.remove(&user_id)
+        else {
+            return Vec::new();
+        };
+
+        user_messages
+            .into_iter()
+            .filter(|message| message.time >= since)
+            .map(|message| MessageId(message.message_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::MessageId;
+
+    use crate::history::MessageHistory;
+
+    #[test]
+    fn test_message_history() {
+        let history = MessageHistory::default();
+        for message_id in 1..=5 {
+            history.record(1, 1, MessageId(message_id), message_id as i64 * 60, 4);
+        }
+        history.record(2, 1, MessageId(6), 300, 4);
+        history.record(1, 2, MessageId(7), 300, 4);
+
+        // Only the most recent messages of the user in the chat within the window are returned
+        assert_eq!(
+            history.take_since(1, 1, 180),
+            [MessageId(3), MessageId(4), MessageId(5)]
+        );
+
+        // Taken messages are forgotten
+        assert!(history.take_since(1, 1, 0).is_empty());
+        assert_eq!(history.take_since(2, 1, 0), [MessageId(6)]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let history = MessageHistory::default();
+        history.record(1, 1, MessageId(1), 100, 10);
+        history.record(1, 1, MessageId(2), 500, 10);
+        history.record(1, 2, MessageId(3), 200, 10);
+        history.record(2, 3, MessageId(4), 300, 10);
+        assert_eq!(history.count_since(1, 1, 0), 2);
+        assert_eq!(history.count_since(1, 1, 200), 1);
+
+        // Users are kept with all their messages as long as their last message is recent
+        history.prune(400);
+        assert_eq!(history.count_since(1, 1, 0), 2);
+        assert_eq!(history.count_since(1, 2, 0), 0);
+        assert_eq!(history.chat_ids(), [1]);
+
+        history.prune(600);
+        assert!(history.chat_ids().is_empty());
+    }
+}
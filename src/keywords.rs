@@ -0,0 +1,158 @@
+use std::{fs, path::Path};
+
+use aho_corasick::AhoCorasick;
+use anyhow::{Context, Result};
+
+use crate::config::KeywordListConfig;
+
+/// A list of literal keywords matched case-insensitively in a single pass
+pub struct KeywordList {
+    pub id: String,
+    pub weight: f64,
+    pub description: Option<String>,
+    deobfuscate: bool,
+    word_boundary: bool,
+    keywords: Vec<String>,
+    automaton: AhoCorasick,
+}
+
+impl KeywordList {
+    /// Builds the list from inline keywords and keyword files, resolving relative
+    /// file paths against the directory of the config file
+    pub fn new(index: usize, list_config: &KeywordListConfig, base_dir: &Path) -> Result<Self> {
+        let mut keywords: Vec<String> = list_config.keywords.clone();
+        for file in &list_config.files {
+            let path = base_dir.join(file);
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read keyword file '{}'", path.display()))?;
+            keywords.extend(parse_keyword_file(&contents));
+        }
+
+        // Keywords and text are lowercased so matching is case-insensitive beyond ASCII
+        let keywords: Vec<String> = keywords
+            .iter()
+            .map(|keyword| keyword.trim().to_lowercase())
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        let automaton = AhoCorasick::new(&keywords)?;
+
+        Ok(KeywordList {
+            id: list_config
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("keywords-{}", index + 1)),
+            weight: list_config.weight,
+            description: list_config.description.clone(),
+            deobfuscate: list_config.deobfuscate,
+            word_boundary: list_config.word_boundary,
+            keywords,
+            automaton,
+        })
+    }
+
+    /// Returns the first keyword found in the text, checking the text
+    /// deobfuscated word by word as well if enabled for this list
+    pub fn find_match(&self, text: &str, deobfuscated_words: &str) -> Option<&str> {
+        self.find_in(text).or_else(|| {
+            if self.deobfuscate {
+                self.find_in(deobfuscated_words)
+            }
+            else {
+                None
+            }
+        })
+    }
+
+    fn find_in(&self, text: &str) -> Option<&str> {
+        let text = text.to_lowercase();
+        self.automaton
+            .find_overlapping_iter(&text)
+            .find(|found| !self.word_boundary || is_word(&text, found.start(), found.end()))
+            .map(|found| self.keywords[found.pattern().as_usize()].as_str())
+    }
+}
+
+// Checks that the match is not directly preceded or followed by a letter or digit
+fn is_word(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+}
+
+/// Parses a plain-text keyword file with one keyword per line, ignoring blank
+/// lines and lines starting with `#`
+fn parse_keyword_file(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the keyword lists matching the text along with the matched keyword
+pub fn find_matches<'a>(
+    text: &str,
+    deobfuscated_words: &str,
+    lists: &'a [KeywordList],
+) -> Vec<(&'a KeywordList, &'a str)> {
+    lists
+        .iter()
+        .filter_map(|list| {
+            list.find_match(text, deobfuscated_words)
+                .map(|keyword| (list, keyword))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        config::{DeobfuscationConfig, KeywordListConfig},
+        keywords::{self, KeywordList},
+        matching,
+    };
+
+    #[test]
+    fn test_keyword_lists() {
+        let list_config = KeywordListConfig {
+            id: Some("crypto".to_string()),
+            weight: 0.5,
+            description: None,
+            keywords: vec![
+                "Crypto".to_string(),
+                "airdrop".to_string(),
+                "scam".to_string(),
+                "free usdt".to_string(),
+            ],
+            files: Vec::new(),
+            deobfuscate: true,
+            word_boundary: true,
+        };
+        let lists = [KeywordList::new(0, &list_config, Path::new(".")).unwrap()];
+        let options = DeobfuscationConfig::default();
+        let find_matches = |text: &str| {
+            let deobfuscated_words = matching::deobfuscate_words(text, &options);
+            keywords::find_matches(text, &deobfuscated_words, &lists)
+                .into_iter()
+                .map(|(_, keyword)| keyword.to_string())
+                .collect::<Vec<String>>()
+        };
+
+        // Case-insensitive whole-word matches
+        assert_eq!(find_matches("Free CRYPTO for everyone"), ["crypto"]);
+
+        // Keywords inside other words do not match
+        assert!(find_matches("cryptography lecture").is_empty());
+
+        // Obfuscated keywords match after deobfuscation
+        assert_eq!(find_matches("a i r d r o p now"), ["airdrop"]);
+        assert_eq!(find_matches("Fr\u{0435}\u{0435} USDT"), ["free usdt"]);
+
+        // Deobfuscation keeps word boundaries, so keywords spanning two words do not match
+        assert!(find_matches("hair drop").is_empty());
+        assert!(find_matches("this is camping gear").is_empty());
+    }
+}
@@ -0,0 +1,33 @@
+pub mod actions;
+pub mod admins;
+pub mod config;
+pub mod document;
+pub mod duplicates;
+pub mod explain;
+pub mod export;
+pub mod federation;
+pub mod flood;
+pub mod handlers;
+pub mod hash;
+pub mod health;
+pub mod history;
+pub mod keywords;
+pub mod matching;
+pub mod metrics;
+pub mod modlog;
+pub mod notices;
+pub mod openai;
+pub mod permissions;
+pub mod privacy;
+pub mod reports;
+pub mod retry;
+pub mod rule_commands;
+pub mod rule_store;
+pub mod scoring;
+pub mod server;
+pub mod settings;
+pub mod state;
+#[cfg(test)]
+mod testing;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
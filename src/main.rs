@@ -1,25 +1,35 @@
-mod actions;
-mod config;
-mod handlers;
-mod matching;
-mod openai;
-
-use std::{path::PathBuf, process};
+use std::{io, path::PathBuf, process, sync::Arc, time::Instant};
 
 use anyhow::Result;
-use clap::Parser;
-use config::Config;
-use teloxide::prelude::*;
-use tracing::{Level, error, info};
-
-pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+use aufseher::{
+    VERSION,
+    config::Config,
+    export::{self, ExportFormat},
+    handlers, health,
+    metrics::METRICS,
+    permissions,
+    privacy::{self, Privacy},
+    server,
+    state::{self, State},
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use teloxide::{
+    RequestError,
+    dispatching::ShutdownToken,
+    prelude::*,
+    types::{AllowedUpdate, UpdateKind},
+    update_listeners::Polling,
+};
+use tokio::signal::{self, unix::SignalKind};
+use tracing::{error, info};
+use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Telegram bot API token
-    #[arg(short = 't', long, env = "TELEGRAM_BOT_TOKEN", required = true)]
-    token: String,
+    /// Telegram bot API token (required to run the bot)
+    #[arg(short = 't', long, env = "TELEGRAM_BOT_TOKEN")]
+    token: Option<String>,
 
     /// OpenAI API key
     #[arg(short = 'o', long, env = "OPENAI_API_KEY")]
@@ -28,22 +38,93 @@ struct Args {
     /// Path to config file
     #[arg(short = 'c', long, default_value = "/etc/aufseher.yaml")]
     config_file: PathBuf,
+
+    /// Log level or filter directives (e.g., `debug` or `aufseher=debug,warn`)
+    #[arg(long, env = "AUFSEHER_LOG_LEVEL", default_value = "info")]
+    log_level: String,
+
+    /// Log format
+    #[arg(long, env = "AUFSEHER_LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Redact message texts and names in logs, and do not store them in the
+    /// data directory (so `aufseher export` has no samples to export)
+    #[arg(long, env = "AUFSEHER_LOG_PRIVACY", value_enum, default_value_t = Privacy::None)]
+    log_privacy: Privacy,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn parse() -> Result<Config> {
-    let args = Args::parse();
-    Ok(Config::new(
-        args.token,
-        args.openai_api_key,
-        args.config_file,
-    )?)
+#[derive(Subcommand)]
+enum Command {
+    /// Export the moderation log as a labeled corpus of spam and pardoned
+    /// false positives for testing rules
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Yaml)]
+        format: ExportFormat,
+
+        /// Output file (stdout if unset)
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
 }
 
-async fn handle_wrapper(bot: Bot, update: Update, config: Config) -> Result<()> {
-    if let Err(error) = handlers::handle_updates(bot, update, &config).await {
+fn init_logging(args: &Args) -> Result<()> {
+    let filter = EnvFilter::try_new(&args.log_level)?;
+    // Subcommands may write their output to stdout, so they log to stderr
+    let writer = match args.command {
+        Some(_) => BoxMakeWriter::new(io::stderr),
+        None => BoxMakeWriter::new(io::stdout),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    match args.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    privacy::init(args.log_privacy);
+    Ok(())
+}
+
+async fn handle_wrapper(
+    bot: Bot,
+    update: Update,
+    config: Arc<Config>,
+    state: Arc<State>,
+) -> Result<()> {
+    let kind = match &update.kind {
+        UpdateKind::Message(_) => "message",
+        UpdateKind::EditedMessage(_) => "edited_message",
+        UpdateKind::MyChatMember(_) => "my_chat_member",
+        UpdateKind::ChatMember(_) => "chat_member",
+        UpdateKind::CallbackQuery(_) => "callback_query",
+        _ => "other",
+    };
+    let start = Instant::now();
+
+    // Receiving an update means polling works
+    state.health.record_update();
+
+    if let Err(error) = handlers::handle_updates(bot, update, &config, &state).await {
+        METRICS.record_error(&error);
         error!("{}", error);
     }
 
+    METRICS.updates.with_label_values(&[kind]).inc();
+    METRICS
+        .handler_latency
+        .with_label_values(&[kind])
+        .observe(start.elapsed().as_secs_f64());
     Ok(())
 }
 
@@ -53,45 +134,163 @@ pub async fn run(config: Config) -> Result<()> {
     // Initialize the bot with token
     let bot = Bot::new(&config.telegram_bot_token);
 
+    // Load the persistent state
+    let state = Arc::new(State::load(&config)?);
+    state.health.set_config_loaded();
+
+    // Serve the metrics and health checks if an address is configured
+    if let Some(address) = config.http.address {
+        server::serve(address, state.clone()).await?;
+    }
+
+    // Check that the Telegram API is reachable even when no updates arrive
+    let (heartbeat_bot, heartbeat_state) = (bot.clone(), state.clone());
+    tokio::spawn(
+        async move { health::run_heartbeat(heartbeat_bot, &heartbeat_state.health).await },
+    );
+
     // Initialize the dispatcher
-    let config_messages = config.clone();
-    let config_edited = config.clone();
+    let config = Arc::new(config);
+    let (config_messages, state_messages) = (config.clone(), state.clone());
+    let (config_edited, state_edited) = (config.clone(), state.clone());
+    let (config_members, state_members) = (config.clone(), state.clone());
+    let (config_admins, state_admins) = (config.clone(), state.clone());
+    let (config_callbacks, state_callbacks) = (config.clone(), state.clone());
     let handler = dptree::entry()
+        .branch(Update::filter_message().endpoint(move |bot, update| {
+            handle_wrapper(bot, update, config_messages.clone(), state_messages.clone())
+        }))
         .branch(
-            Update::filter_message()
-                .endpoint(move |bot, update| handle_wrapper(bot, update, config_messages.clone())),
+            Update::filter_edited_message().endpoint(move |bot, update| {
+                handle_wrapper(bot, update, config_edited.clone(), state_edited.clone())
+            }),
         )
         .branch(
-            Update::filter_edited_message()
-                .endpoint(move |bot, update| handle_wrapper(bot, update, config_edited.clone())),
+            Update::filter_my_chat_member().endpoint(move |bot, update| {
+                handle_wrapper(bot, update, config_members.clone(), state_members.clone())
+            }),
+        )
+        .branch(Update::filter_chat_member().endpoint(move |bot, update| {
+            handle_wrapper(bot, update, config_admins.clone(), state_admins.clone())
+        }))
+        .branch(
+            Update::filter_callback_query().endpoint(move |bot, update| {
+                handle_wrapper(
+                    bot,
+                    update,
+                    config_callbacks.clone(),
+                    state_callbacks.clone(),
+                )
+            }),
         );
 
-    // Start the dispatcher
+    // Save the state periodically in case the bot is killed
+    let (autosave_state, autosave_config) = (state.clone(), config.clone());
+    tokio::spawn(async move { state::run_autosave(&autosave_state, &autosave_config).await });
+
+    // Check the rights of the bot in the chats it knows of
+    let (check_bot, check_config) = (bot.clone(), config.clone());
+    let known_chats = state.history.chat_ids();
+    tokio::spawn(async move {
+        permissions::check_known_chats(&check_bot, &check_config, known_chats).await
+    });
+
+    // Start the dispatcher, recording failed polls for the health checks
     info!("Initialization complete, starting to handle updates");
-    Dispatcher::builder(bot, handler)
+    // Member updates are only sent by Telegram if they are requested explicitly
+    let listener = Polling::builder(bot.clone())
+        .timeout(health::POLLING_TIMEOUT)
+        .allowed_updates(vec![
+            AllowedUpdate::Message,
+            AllowedUpdate::EditedMessage,
+            AllowedUpdate::MyChatMember,
+            AllowedUpdate::ChatMember,
+            AllowedUpdate::CallbackQuery,
+        ])
+        .delete_webhook()
+        .await
+        .build();
+    let listener = health::ObservedListener::new(listener, state.clone());
+    let listener_state = state.clone();
+    let listener_error_handler = Arc::new(move |error: RequestError| {
+        let state = listener_state.clone();
+        async move {
+            state.health.record_poll_error();
+            METRICS
+                .telegram_errors
+                .with_label_values(&["get_updates"])
+                .inc();
+            error!("Failed to get updates: {}", error);
+        }
+    });
+    let mut dispatcher = Dispatcher::builder(bot, handler)
         .enable_ctrlc_handler()
-        .build()
-        .dispatch()
+        .build();
+    tokio::spawn(shutdown_on_sigterm(dispatcher.shutdown_token()));
+    dispatcher
+        .dispatch_with_listener(listener, listener_error_handler)
         .await;
 
-    Ok(())
+    // Save the state after the dispatcher was stopped
+    state.save(&config)
+}
+
+/// Stops the dispatcher on SIGTERM, which Docker and systemd send to stop the
+/// bot, so that the state is saved as on Ctrl+C
+async fn shutdown_on_sigterm(shutdown_token: ShutdownToken) {
+    match signal::unix::signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+            info!("Received SIGTERM, shutting down");
+            if let Ok(shutdown) = shutdown_token.shutdown() {
+                shutdown.await;
+            }
+        }
+        Err(error) => error!("Failed to listen for SIGTERM: {}", error),
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+    let args = Args::parse();
+    if let Err(error) = init_logging(&args) {
+        eprintln!("Invalid log level '{}': {}", args.log_level, error);
+        process::exit(1);
+    }
+
+    // The token is only needed to run the bot, not for the subcommands
+    let token = match (&args.command, args.token) {
+        (Some(_), token) => token.unwrap_or_default(),
+        (None, Some(token)) => token,
+        (None, None) => {
+            eprintln!("A Telegram bot API token is required (--token or TELEGRAM_BOT_TOKEN)");
+            process::exit(2);
+        }
+    };
 
-    match parse() {
+    match Config::new(token, args.openai_api_key, args.config_file) {
         Err(error) => {
             error!("Program initialization error: {}", error);
             process::exit(1);
         }
-        Ok(config) => process::exit(match run(config).await {
-            Ok(_) => 0,
-            Err(error) => {
-                error!("{}", error);
-                1
-            }
+        Ok(config) => process::exit(match args.command {
+            Some(Command::Export {
+                format,
+                output,
+            }) => match export::export(&config, format, output.as_deref()) {
+                Ok(()) => 0,
+                Err(error) => {
+                    error!("{:#}", error);
+                    1
+                }
+            },
+            None => match run(config).await {
+                Ok(_) => 0,
+                Err(error) => {
+                    error!("{}", error);
+                    1
+                }
+            },
         }),
     }
 }
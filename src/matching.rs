@@ -1,33 +1,30 @@
-use fancy_regex::Regex;
+use std::sync::{
+    LazyLock,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
 
-pub fn is_match(input: &str, regexes: &Vec<Regex>) -> Result<Option<Regex>, fancy_regex::Error> {
-    for regex in regexes {
-        if regex.is_match(input)? {
-            return Ok(Some(regex.clone()));
-        }
-    }
-    return Ok(None);
-}
+use fancy_regex::{Regex, RegexBuilder, RuntimeError};
+use regex::{RegexSet, RegexSetBuilder};
+use tracing::{debug, warn};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
 
-pub fn is_match_obfuscated(
-    input: &str,
-    regexes: &Vec<Regex>,
-) -> Result<Option<Regex>, fancy_regex::Error> {
-    // Deobfuscate the message text
-    let input = deobfuscate_message_text(input)?;
+use crate::{
+    config::{DeobfuscationConfig, RegexLimitsConfig, RuleConfig},
+    metrics::METRICS,
+    privacy,
+};
 
-    for regex in regexes {
-        if regex.is_match(&input)? {
-            return Ok(Some(regex.clone()));
-        }
-    }
-    return Ok(None);
-}
+// Size limit for the combined automaton of all rules in a set
+const REGEX_SET_SIZE_LIMIT: usize = 64 * 1024 * 1024;
 
-fn deobfuscate_message_text(text: &str) -> Result<String, fancy_regex::Error> {
-    // Define patterns for spaces, invisible characters, and emojis
-    let space_pattern = r"[ \n\t\u{00A0}\u{180E}\u{200B}\u{200C}\u{200D}\u{2060}\u{2062}\u{FEFF}]";
-    let emoji_pattern = concat!(
+// Patterns for spaces, invisible characters, and emojis
+static SPACE_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"[ \n\t\u{00A0}\u{180E}\u{200B}\u{200C}\u{200D}\u{2060}\u{2062}\u{FEFF}]")
+        .unwrap()
+});
+static EMOJI_RE: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(concat!(
         r"[\u{1F600}-\u{1F64F}]|[\u{1F300}-\u{1F5FF}]|",
         r"[\u{1F680}-\u{1F6FF}]|[\u{1F700}-\u{1F77F}]|",
         r"[\u{1F780}-\u{1F7FF}]|[\u{1F800}-\u{1F8FF}]|",
@@ -37,30 +34,329 @@ fn deobfuscate_message_text(text: &str) -> Result<String, fancy_regex::Error> {
         r"[\u{2B50}-\u{2B55}]|[\u{1F1E6}-\u{1F1FF}]|",
         r"[\u{1F004}]|[\u{1F0CF}]|[\u{1F18E}]|",
         r"[\u{1F191}-\u{1F19A}]|[\u{1F1E6}-\u{1F1FF}]"
-    );
-    let non_text_pattern = r"[^\p{L}\p{N}\p{P}\p{Z}]";
+    ))
+    .unwrap()
+});
+static NON_TEXT_RE: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"[^\p{L}\p{N}\p{P}\p{Z}]").unwrap());
+
+/// A regex rule with the weight it adds to the spam score of a match
+pub struct Rule {
+    pub id: String,
+    pub regex: Regex,
+    pub weight: f64,
+    pub description: Option<String>,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
 
-    // Create regex objects
-    let space_re = Regex::new(space_pattern)?;
-    let emoji_re = Regex::new(emoji_pattern)?;
-    let non_text_re = Regex::new(non_text_pattern)?;
+/// A list of rules compiled once for matching
+///
+/// Rules that the `regex` crate can handle are combined into a single
+/// `RegexSet`, which scans the input once using literal prefilters and never
+/// backtracks. Only rules using fancy-regex features (e.g., lookaround or
+/// backreferences) are evaluated one by one with the backtracking engine.
+///
+/// Backtracking is capped by the configured limit. A rule exceeding the limit
+/// is logged, counted and treated as not matching, and can optionally be
+/// disabled after a number of occurrences.
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    set: RegexSet,
+    set_rules: Vec<usize>,
+    fancy_rules: Vec<usize>,
+    limit_exceeded: Vec<AtomicU64>,
+    disabled: Vec<AtomicBool>,
+    disable_after: u64,
+}
+
+impl RuleSet {
+    /// Compiles the rules, naming rules without an ID after their kind and position
+    pub fn new(
+        kind: &str,
+        rule_configs: &[RuleConfig],
+        limits: &RegexLimitsConfig,
+    ) -> Result<RuleSet, fancy_regex::Error> {
+        let rules = rule_configs
+            .iter()
+            .enumerate()
+            .map(|(index, rule_config)| {
+                Ok(Rule {
+                    id: rule_config
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| format!("{}-{}", kind, index + 1)),
+                    regex: RegexBuilder::new(&rule_config.pattern)
+                        .backtrack_limit(limits.backtrack_limit)
+                        .build()?,
+                    weight: rule_config.weight,
+                    description: rule_config.description.clone(),
+                })
+            })
+            .collect::<Result<Vec<Rule>, fancy_regex::Error>>()?;
+        let patterns: Vec<String> = rules.iter().map(|rule| rule.as_str().to_string()).collect();
+        let limit_exceeded = patterns.iter().map(|_| AtomicU64::new(0)).collect();
+        let disabled = patterns.iter().map(|_| AtomicBool::new(false)).collect();
+
+        // Sort rules by whether the linear-time engine supports them
+        let (set_rules, fancy_rules): (Vec<usize>, Vec<usize>) =
+            (0..patterns.len()).partition(|&index| regex::Regex::new(&patterns[index]).is_ok());
+
+        let set = match RegexSetBuilder::new(set_rules.iter().map(|&index| &patterns[index]))
+            .size_limit(REGEX_SET_SIZE_LIMIT)
+            .build()
+        {
+            Ok(set) => set,
+            Err(error) => {
+                warn!(
+                    "Failed to combine rules into a regex set, falling back to backtracking: {}",
+                    error
+                );
+                return Ok(RuleSet {
+                    rules,
+                    set: RegexSet::empty(),
+                    set_rules: Vec::new(),
+                    fancy_rules: (0..patterns.len()).collect(),
+                    limit_exceeded,
+                    disabled,
+                    disable_after: limits.disable_after,
+                });
+            }
+        };
+
+        Ok(RuleSet {
+            rules,
+            set,
+            set_rules,
+            fancy_rules,
+            limit_exceeded,
+            disabled,
+            disable_after: limits.disable_after,
+        })
+    }
+
+    /// Returns whether the set contains a rule with the given ID
+    pub fn contains(&self, id: &str) -> bool {
+        self.rules.iter().any(|rule| rule.id == id)
+    }
+
+    fn is_fancy_match(&self, index: usize, input: &str) -> Result<bool, fancy_regex::Error> {
+        if self.disabled[index].load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+
+        match self.rules[index].regex.is_match(input) {
+            Ok(matched) => Ok(matched),
+            Err(fancy_regex::Error::RuntimeError(RuntimeError::BacktrackLimitExceeded)) => {
+                let count = self.limit_exceeded[index].fetch_add(1, Ordering::Relaxed) + 1;
+                METRICS
+                    .backtrack_limit_exceeded
+                    .with_label_values(&[&self.rules[index].id])
+                    .inc();
+                warn!(
+                    "Rule '{}' exceeded the backtrack limit ({} times), treating as no match",
+                    self.rules[index].id, count
+                );
 
+                if self.disable_after > 0 && count >= self.disable_after {
+                    self.disabled[index].store(true, Ordering::Relaxed);
+                    warn!(
+                        "Rule '{}' has been disabled after exceeding the backtrack limit {} times",
+                        self.rules[index].id, count
+                    );
+                }
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns all rules that match the input in configuration order
+    pub fn matches(&self, input: &str) -> Result<Vec<&Rule>, fancy_regex::Error> {
+        let mut matched: Vec<usize> = self
+            .set
+            .matches(input)
+            .iter()
+            .map(|index| self.set_rules[index])
+            .collect();
+
+        for &index in &self.fancy_rules {
+            if self.is_fancy_match(index, input)? {
+                matched.push(index);
+            }
+        }
+
+        matched.sort_unstable();
+        Ok(matched
+            .into_iter()
+            .map(|index| &self.rules[index])
+            .collect())
+    }
+}
+
+pub fn deobfuscate_message_text(text: &str, options: &DeobfuscationConfig) -> String {
+    let mut stages = deobfuscate_message_text_stages(text, options);
+    stages.pop().map(|(_, text)| text).unwrap_or_default()
+}
+
+/// Deobfuscates every word of the text on its own, keeping the spaces between
+/// words so that keywords can be matched as whole words
+///
+/// Runs of single characters are joined, as spacing out the letters of a word
+/// (e.g., `a i r d r o p`) is a common obfuscation.
+pub fn deobfuscate_words(text: &str, options: &DeobfuscationConfig) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut previous_single = false;
+    for token in text.split_whitespace() {
+        let word = deobfuscate_message_text(token, options);
+        if word.is_empty() {
+            continue;
+        }
+        let single = word.chars().count() == 1;
+        if single
+            && previous_single
+            && let Some(last) = words.last_mut()
+        {
+            last.push_str(&word);
+        }
+        else {
+            words.push(word);
+        }
+        previous_single = single;
+    }
+    words.join(" ")
+}
+
+/// Deobfuscates the text and returns the output of every enabled stage in order
+pub fn deobfuscate_message_text_stages(
+    text: &str,
+    options: &DeobfuscationConfig,
+) -> Vec<(&'static str, String)> {
+    let mut stages = Vec::new();
+    let mut text = text.to_string();
+
+    // Fold compatibility characters (e.g., fullwidth and mathematical letters)
+    if options.nfkc {
+        text = text.nfkc().collect();
+        stages.push(("nfkc", text.clone()));
+    }
+
+    // Replace lookalikes from other scripts with the Latin letters they imitate
+    if options.confusables {
+        text = fold_confusables(&text);
+        stages.push(("confusables", text.clone()));
+    }
+
+    // Replace digits and symbols used as letters
+    if options.leetspeak {
+        text = fold_leetspeak(&text);
+        stages.push(("leetspeak", text.clone()));
+    }
+
+    text = strip_non_text(&text);
+    stages.push(("strip", text.clone()));
+
+    if options.case_fold {
+        text = text.to_lowercase();
+        stages.push(("case_fold", text.clone()));
+    }
+
+    for (stage, output) in &stages {
+        debug!(
+            "Deobfuscation stage '{}' output: '{}'",
+            stage,
+            privacy::redact(output)
+        );
+    }
+
+    stages
+}
+
+fn strip_non_text(text: &str) -> String {
     // Remove spaces, invisible characters, and emojis
-    let text = space_re.replace_all(text, "");
-    let text = emoji_re.replace_all(&text, "");
-    let text = non_text_re.replace_all(&text, "");
+    let text = SPACE_RE.replace_all(text, "");
+    let text = EMOJI_RE.replace_all(&text, "");
+    let text = NON_TEXT_RE.replace_all(&text, "");
+
+    text.to_string()
+}
+
+fn fold_confusables(text: &str) -> String {
+    // Only fold words that mix Latin letters with other characters or whose
+    // letters all imitate Latin ones (e.g., Cyrillic "сору"), so that text
+    // written in another script (e.g., "Привет") stays intact
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            let mixes_latin = word.chars().any(|c| c.is_ascii_alphabetic());
+            let imitates_latin = word.chars().any(char::is_alphabetic)
+                && word
+                    .chars()
+                    .filter(|c| c.is_alphabetic())
+                    .all(|c| c.is_ascii() || latin_prototype(c).is_some());
+            if !mixes_latin && !imitates_latin {
+                return word.to_string();
+            }
+
+            word.chars()
+                .map(|c| {
+                    if c.is_ascii() {
+                        return c.to_string();
+                    }
+                    latin_prototype(c).unwrap_or_else(|| c.to_string())
+                })
+                .collect()
+        })
+        .collect()
+}
 
-    Ok(text.to_string())
+/// Returns the UTS #39 skeleton of a character if it maps to plain ASCII letters/digits
+fn latin_prototype(c: char) -> Option<String> {
+    let prototype: String = skeleton(&c.to_string()).collect();
+    (!prototype.is_empty() && prototype.chars().all(|p| p.is_ascii_alphanumeric()))
+        .then_some(prototype)
+}
+
+fn fold_leetspeak(text: &str) -> String {
+    // Only fold words that contain letters so that numbers stay intact
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            if !word.chars().any(char::is_alphabetic) {
+                return word.to_string();
+            }
+
+            word.chars()
+                .map(|c| match c {
+                    '0' => 'o',
+                    '1' | '!' => 'i',
+                    '3' => 'e',
+                    '4' | '@' => 'a',
+                    '5' | '$' => 's',
+                    '7' => 't',
+                    '8' => 'b',
+                    '9' => 'g',
+                    '|' => 'l',
+                    _ => c,
+                })
+                .collect()
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use std::{fs, path::PathBuf};
 
-    use fancy_regex::Regex;
     use serde::Deserialize;
 
-    use crate::matching;
+    use crate::{
+        config::{DeobfuscationConfig, RegexLimitsConfig, RuleConfig},
+        matching::{self, RuleSet},
+        metrics::METRICS,
+    };
 
     #[derive(Debug, Deserialize)]
     struct Tests {
@@ -70,11 +366,27 @@ mod tests {
 
     #[derive(Debug, Deserialize)]
     struct AufseherConfig {
-        name_regexes: Vec<String>,
-        message_regexes: Vec<String>,
+        name_regexes: Vec<RuleConfig>,
+        message_regexes: Vec<RuleConfig>,
+        #[serde(default)]
+        deobfuscation: DeobfuscationConfig,
+        #[serde(default)]
+        regex_limits: RegexLimitsConfig,
         tests: Tests,
     }
 
+    fn rule_configs(patterns: &[String]) -> Vec<RuleConfig> {
+        patterns
+            .iter()
+            .map(|pattern| RuleConfig {
+                pattern: pattern.clone(),
+                id: None,
+                weight: 1.0,
+                description: None,
+            })
+            .collect()
+    }
+
     #[test]
     fn test_regexes() {
         let mut config_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -82,63 +394,135 @@ mod tests {
         let file_contents = fs::read_to_string(config_path).unwrap();
         let config: AufseherConfig = serde_yaml::from_str(&file_contents).unwrap();
 
-        let name_regexes: Vec<Regex> = config
-            .name_regexes
-            .iter()
-            .try_fold(Vec::new(), |mut acc, r| {
-                acc.push(Regex::new(r)?);
-                Ok::<_, fancy_regex::Error>(acc)
-            })
-            .unwrap();
+        let name_regexes =
+            RuleSet::new("name", &config.name_regexes, &config.regex_limits).unwrap();
 
-        let message_regexes: Vec<Regex> = config
-            .message_regexes
-            .iter()
-            .try_fold(Vec::new(), |mut acc, r| {
-                acc.push(Regex::new(r)?);
-                Ok::<_, fancy_regex::Error>(acc)
-            })
-            .unwrap();
+        let message_regexes =
+            RuleSet::new("message", &config.message_regexes, &config.regex_limits).unwrap();
 
         for username in config.tests.usernames {
-            let mut matched = matching::is_match(&username, &name_regexes).unwrap();
-
-            if let Some(pattern) = matched.as_ref() {
-                println!(
-                    "Username '{}' matched pattern '{}'",
-                    username,
-                    pattern.as_str()
-                );
+            let mut matched = name_regexes.matches(&username).unwrap();
+            if matched.is_empty() {
+                let deobfuscated =
+                    matching::deobfuscate_message_text(&username, &config.deobfuscation);
+                matched = name_regexes.matches(&deobfuscated).unwrap();
             }
-            else {
-                matched = matching::is_match_obfuscated(&username, &name_regexes).unwrap();
+
+            for rule in &matched {
+                println!("Username '{}' matched rule '{}'", username, rule.id);
             }
 
             assert!(
-                matched.is_some(),
+                !matched.is_empty(),
                 "Username '{}' did not match any of the provided patterns",
                 username
             );
         }
 
         for message in config.tests.messages {
-            let matched = matching::is_match(&message, &message_regexes)
-                .unwrap()
-                .or_else(|| matching::is_match_obfuscated(&message, &message_regexes).unwrap());
-
-            if let Some(pattern) = matched.as_ref() {
-                println!(
-                    "Message '{}' matched pattern '{}'",
-                    message,
-                    pattern.as_str()
-                );
+            let mut matched = message_regexes.matches(&message).unwrap();
+            if matched.is_empty() {
+                let deobfuscated =
+                    matching::deobfuscate_message_text(&message, &config.deobfuscation);
+                matched = message_regexes.matches(&deobfuscated).unwrap();
+            }
+
+            for rule in &matched {
+                println!("Message '{}' matched rule '{}'", message, rule.id);
             }
 
             assert!(
-                matched.is_some(),
+                !matched.is_empty(),
                 "Message '{}' did not match any of the provided patterns",
                 message
             );
         }
     }
+
+    #[test]
+    fn test_deobfuscation_stages() {
+        let options = DeobfuscationConfig {
+            nfkc: true,
+            confusables: true,
+            leetspeak: true,
+            case_fold: true,
+        };
+
+        // Roman numeral, Cyrillic lookalikes and fullwidth letters
+        let text = matching::deobfuscate_message_text("ⅽrурtо ＦＲＥＥ", &options);
+        assert_eq!(text, "cryptofree");
+
+        // Leetspeak is folded in words but numbers stay intact
+        let text = matching::deobfuscate_message_text("Fr33 m0ney 1234567890", &options);
+        assert_eq!(text, "freemoney1234567890");
+
+        // Words made up entirely of Cyrillic lookalikes are folded
+        let text = matching::deobfuscate_message_text("АВС сору", &options);
+        assert_eq!(text, "abccopy");
+
+        // Text written entirely in another script is not folded
+        let text = matching::deobfuscate_message_text("Привет", &options);
+        assert_eq!(text, "привет");
+    }
+
+    #[test]
+    fn test_rule_set_order() {
+        // The lookaround rule can only be evaluated by the backtracking engine
+        let patterns = vec![
+            "spam".to_string(),
+            "(?<=free )money".to_string(),
+            "money".to_string(),
+        ];
+        let rules = RuleSet::new(
+            "message",
+            &rule_configs(&patterns),
+            &RegexLimitsConfig::default(),
+        )
+        .unwrap();
+
+        let matched: Vec<&str> = rules
+            .matches("free money")
+            .unwrap()
+            .iter()
+            .map(|rule| rule.as_str())
+            .collect();
+        assert_eq!(matched, ["(?<=free )money", "money"]);
+
+        let matched: Vec<&str> = rules
+            .matches("spam for free money")
+            .unwrap()
+            .iter()
+            .map(|rule| rule.as_str())
+            .collect();
+        assert_eq!(matched, patterns);
+
+        assert!(rules.matches("hello").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backtrack_limit() {
+        let patterns = vec![r"(a+)+\1b".to_string(), "a{30}c".to_string()];
+        let limits = RegexLimitsConfig {
+            backtrack_limit: 10_000,
+            disable_after: 2,
+        };
+        let rules = RuleSet::new("runaway", &rule_configs(&patterns), &limits).unwrap();
+        let input = "a".repeat(30) + "c";
+        let exceeded = METRICS
+            .backtrack_limit_exceeded
+            .with_label_values(&["runaway-1"]);
+        let initial = exceeded.get();
+
+        // The runaway rule is skipped and the next rule still matches
+        let matched = rules.matches(&input).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].as_str(), "a{30}c");
+        assert_eq!(exceeded.get() - initial, 1);
+
+        // The rule is disabled once it exceeded the limit often enough
+        rules.matches(&input).unwrap();
+        assert_eq!(exceeded.get() - initial, 2);
+        rules.matches(&input).unwrap();
+        assert_eq!(exceeded.get() - initial, 2);
+    }
 }
@@ -0,0 +1,153 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use teloxide::RequestError;
+use tracing::error;
+
+use crate::scoring::Score;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Prometheus metrics of the bot, collected whether or not they are served
+pub struct Metrics {
+    registry: Registry,
+    /// Updates processed by update kind
+    pub updates: IntCounterVec,
+    /// Time taken to handle an update by update kind
+    pub handler_latency: HistogramVec,
+    /// Messages and names matched by each rule or keyword list
+    pub rule_matches: IntCounterVec,
+    /// Matches of each rule aborted because the backtrack limit was exceeded
+    pub backtrack_limit_exceeded: IntCounterVec,
+    /// Actions taken by kind
+    pub actions: IntCounterVec,
+    /// LLM requests by result (`spam`, `not_spam` or `error`)
+    pub llm_requests: IntCounterVec,
+    pub llm_latency: Histogram,
+    /// Tokens used by the LLM by kind (`prompt` or `completion`)
+    pub llm_tokens: IntCounterVec,
+    /// Failed Telegram requests by error kind
+    pub telegram_errors: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, label: &str| {
+            let counter = IntCounterVec::new(Opts::new(name, help), &[label]).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+
+        let updates = counter("aufseher_updates_total", "Updates processed", "kind");
+        let rule_matches = counter(
+            "aufseher_rule_matches_total",
+            "Messages and names matched by each rule",
+            "rule_id",
+        );
+        let backtrack_limit_exceeded = counter(
+            "aufseher_backtrack_limit_exceeded_total",
+            "Matches of each rule aborted because the backtrack limit was exceeded",
+            "rule_id",
+        );
+        let actions = counter("aufseher_actions_total", "Actions taken", "action");
+        let llm_requests = counter("aufseher_llm_requests_total", "LLM requests", "result");
+        let llm_tokens = counter(
+            "aufseher_llm_tokens_total",
+            "Tokens used by the LLM",
+            "kind",
+        );
+        let telegram_errors = counter(
+            "aufseher_telegram_errors_total",
+            "Failed Telegram requests",
+            "kind",
+        );
+
+        let handler_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "aufseher_handler_latency_seconds",
+                "Time taken to handle an update",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(handler_latency.clone()))
+            .unwrap();
+        let llm_latency = Histogram::with_opts(
+            HistogramOpts::new("aufseher_llm_latency_seconds", "Time taken by LLM requests")
+                .buckets(vec![0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(llm_latency.clone())).unwrap();
+
+        Metrics {
+            registry,
+            updates,
+            handler_latency,
+            rule_matches,
+            backtrack_limit_exceeded,
+            actions,
+            llm_requests,
+            llm_latency,
+            llm_tokens,
+            telegram_errors,
+        }
+    }
+
+    /// Counts the rules and keyword lists that matched
+    pub fn record_score(&self, score: &Score) {
+        for rule_id in score
+            .signals
+            .iter()
+            .filter_map(|signal| signal.rule_id.as_ref())
+        {
+            self.rule_matches.with_label_values(&[rule_id]).inc();
+        }
+    }
+
+    /// Counts the error if it was caused by a Telegram request
+    pub fn record_error(&self, error: &anyhow::Error) {
+        if let Some(request_error) = error.downcast_ref::<RequestError>() {
+            self.record_request_error(request_error);
+        }
+    }
+
+    /// Counts a failed Telegram request
+    pub fn record_request_error(&self, error: &RequestError) {
+        let kind = match error {
+            RequestError::Api(_) => "api",
+            RequestError::MigrateToChatId(_) => "migrate_to_chat_id",
+            RequestError::RetryAfter(_) => "retry_after",
+            RequestError::Network(_) => "network",
+            RequestError::InvalidJson {
+                ..
+            } => "invalid_json",
+            RequestError::Io(_) => "io",
+        };
+        self.telegram_errors.with_label_values(&[kind]).inc();
+    }
+
+    /// Encodes all metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(error) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Failed to encode metrics: {}", error);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::METRICS;
+
+    #[test]
+    fn test_encode_metrics() {
+        METRICS.actions.with_label_values(&["ban"]).inc();
+        let encoded = METRICS.encode();
+        assert!(encoded.contains("aufseher_actions_total{action=\"ban\"}"));
+    }
+}
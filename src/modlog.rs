@@ -0,0 +1,145 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, sync::Mutex};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{retry::Outcome, rule_store::RuleKind};
+
+pub const MODERATION_LOG_FILE: &str = "moderation.jsonl";
+
+/// An action taken against a user, including the steps of it that failed
+///
+/// Names and texts are only stored if `--log-privacy` does not redact them,
+/// so `aufseher export` cannot turn entries recorded with it into samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModerationEntry {
+    /// Unix timestamp of the action
+    pub time: i64,
+    pub chat_id: i64,
+    pub user_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i32>,
+    /// Name of the user at the time of the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_name: Option<String>,
+    pub action: String,
+    pub reason: String,
+    /// Text of the message that caused the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Kinds of regex rules that matched the name or text of the entry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rule_kinds: Vec<RuleKind>,
+    /// Whether the punishment itself succeeded
+    pub taken: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+}
+
+impl ModerationEntry {
+    pub fn new(chat_id: i64, user_id: u64, action: &str, reason: &str, outcome: &Outcome) -> Self {
+        Self {
+            time: Utc::now().timestamp(),
+            chat_id,
+            user_id,
+            message_id: None,
+            user_name: None,
+            action: action.to_string(),
+            reason: reason.to_string(),
+            text: None,
+            rule_kinds: Vec::new(),
+            taken: outcome.taken,
+            failures: outcome.failure_descriptions(),
+        }
+    }
+}
+
+/// Append-only log written as JSON lines to the data directory if one is
+/// configured, such as the moderation log
+#[derive(Default)]
+pub struct JsonLinesLog {
+    /// Name of the log in warnings (e.g., `moderation log`)
+    name: &'static str,
+    path: Option<PathBuf>,
+    lock: Mutex<()>,
+}
+
+impl JsonLinesLog {
+    pub fn new(name: &'static str, path: Option<PathBuf>) -> Self {
+        Self {
+            name,
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Appends an entry to the log, logging instead of failing if it cannot be written
+    pub fn record<T: Serialize>(&self, entry: &T) {
+        if let Err(error) = self.append(entry) {
+            warn!("Failed to write the {}: {:#}", self.name, error);
+        }
+    }
+
+    fn append<T: Serialize>(&self, entry: &T) -> Result<()> {
+        let Some(path) = &self.path
+        else {
+            return Ok(());
+        };
+
+        let line = serde_json::to_string(entry)?;
+        let _lock = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open '{}'", path.display()))?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        modlog::{JsonLinesLog, ModerationEntry},
+        retry::Outcome,
+        testing::TempDir,
+    };
+
+    #[test]
+    fn test_moderation_log() {
+        let dir = TempDir::new("modlog");
+        let path = dir.path().join("moderation.jsonl");
+        let log = JsonLinesLog::new("moderation log", Some(path.clone()));
+
+        let outcome = Outcome {
+            taken: true,
+            ..Outcome::default()
+        };
+        let mut entry = ModerationEntry::new(-100, 42, "ban", "spam score 3", &outcome);
+        entry.text = Some("Buy now".to_string());
+        log.record(&entry);
+        log.record(&ModerationEntry::new(
+            -100,
+            43,
+            "mute",
+            "flood",
+            &Outcome::default(),
+        ));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let entries: Vec<ModerationEntry> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].text.as_deref(), Some("Buy now"));
+        assert!(entries[0].taken);
+        assert!(!entries[1].taken);
+        assert!(!contents.contains("failures"));
+    }
+}
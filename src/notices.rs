@@ -0,0 +1,141 @@
+use anyhow::Result;
+use teloxide::{
+    prelude::*,
+    types::{ParseMode, User},
+    utils::markdown::escape,
+};
+use tokio::time::{self, Duration};
+use tracing::warn;
+
+use crate::config::Config;
+
+/// Languages with built-in notice templates
+pub const LANGUAGES: [&str; 2] = ["en", "de"];
+
+/// A notice about an action taken against a user
+pub struct Notice<'a> {
+    /// Kind of the notice (`delete`, `mute`, `ban` or `flood`)
+    pub kind: &'a str,
+    pub user: &'a User,
+    /// Description of the rule that caused the action
+    pub rule: &'a str,
+    /// Duration of the action in seconds
+    pub duration: u64,
+}
+
+impl Notice<'_> {
+    /// Fills in the placeholders of a MarkdownV2 template, escaping the values
+    pub fn render(&self, template: &str) -> String {
+        template
+            .replace("{user}", &escape(&self.user.full_name()))
+            .replace("{user_id}", &self.user.id.to_string())
+            .replace("{rule}", &escape(self.rule))
+            .replace("{action}", &escape(self.kind))
+            .replace("{duration}", &(self.duration / 60).to_string())
+    }
+}
+
+/// Returns the built-in template for a kind of notice in a language, if any
+///
+/// No notice is posted for deleted messages unless a template is configured.
+fn builtin_template(language: &str, kind: &str) -> Option<&'static str> {
+    match (language, kind) {
+        ("de", "mute") => Some(
+            "Benutzer {user_id} \\(||{user}||\\) wurde für {duration} Minuten stummgeschaltet\\.",
+        ),
+        ("de", "ban") => Some("Benutzer {user_id} \\(||{user}||\\) wurde gesperrt\\."),
+        ("de", "flood") => Some(
+            "Benutzer {user_id} \\(||{user}||\\) wurde wegen Flooding für {duration} Minuten stummgeschaltet\\.",
+        ),
+        (_, "mute") => {
+            Some("User {user_id} \\(||{user}||\\) has been muted for {duration} minutes\\.")
+        }
+        (_, "ban") => Some("User {user_id} \\(||{user}||\\) has been banned\\."),
+        (_, "flood") => Some(
+            "User {user_id} \\(||{user}||\\) has been muted for {duration} minutes for flooding\\.",
+        ),
+        _ => None,
+    }
+}
+
+/// Posts a notice to a chat according to its notice settings and schedules
+/// its deletion if configured
+///
+/// Whether notices are enabled in the chat is checked by the caller, since
+/// chat admins can override it.
+pub async fn send_notice(
+    bot: &Bot,
+    chat_id: ChatId,
+    notice: &Notice<'_>,
+    config: &Config,
+) -> Result<()> {
+    let notices = config.notices_for(chat_id);
+    let Some(template) = notices
+        .templates
+        .get(notice.kind)
+        .map(String::as_str)
+        .or_else(|| builtin_template(&notices.language, notice.kind))
+    else {
+        return Ok(());
+    };
+
+    let sent = bot
+        .send_message(chat_id, notice.render(template))
+        .parse_mode(ParseMode::MarkdownV2)
+        .disable_notification(true)
+        .await?;
+
+    // Delete the notice in the background so the action is not delayed
+    if notices.delete_after > 0 {
+        let bot = bot.clone();
+        let delete_after = notices.delete_after;
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(delete_after)).await;
+            if let Err(error) = bot.delete_message(chat_id, sent.id).await {
+                warn!("Failed to delete notice in {}: {}", chat_id, error);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{User, UserId};
+
+    use crate::notices::{self, Notice};
+
+    #[test]
+    fn test_render_notice() {
+        let user = User {
+            id: UserId(42),
+            is_bot: false,
+            first_name: "Spam.Bot".to_string(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        };
+        let notice = Notice {
+            kind: "mute",
+            user: &user,
+            rule: "crypto-giveaway",
+            duration: 3600,
+        };
+
+        // Values are escaped for MarkdownV2
+        assert_eq!(
+            notice.render(notices::builtin_template("en", "mute").unwrap()),
+            "User 42 \\(||Spam\\.Bot||\\) has been muted for 60 minutes\\."
+        );
+        assert_eq!(
+            notice.render("{user} ({rule}): {action}"),
+            "Spam\\.Bot (crypto\\-giveaway): mute"
+        );
+
+        // Deletions are silent unless a template is configured
+        assert!(notices::builtin_template("de", "delete").is_none());
+    }
+}
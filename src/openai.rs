@@ -1,8 +1,11 @@
+use std::time::Instant;
+
 use anyhow::Result;
-use reqwest;
 use serde::Deserialize;
 use serde_json::json;
 
+use crate::metrics::METRICS;
+
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct OpenAICompletionsResponse {
@@ -44,20 +47,33 @@ struct OpenAICheckSpamResponse {
 }
 
 pub async fn openai_check_is_message_spam(message: &str, openai_api_key: &str) -> Result<bool> {
+    let start = Instant::now();
+    let result = openai_check_is_message_spam_unmeasured(message, openai_api_key).await;
+    METRICS.llm_latency.observe(start.elapsed().as_secs_f64());
+
+    let label = match &result {
+        Ok(true) => "spam",
+        Ok(false) => "not_spam",
+        Err(_) => "error",
+    };
+    METRICS.llm_requests.with_label_values(&[label]).inc();
+    result
+}
+
+async fn openai_check_is_message_spam_unmeasured(
+    message: &str,
+    openai_api_key: &str,
+) -> Result<bool> {
     let response = openai_complete_single_message(message, openai_api_key).await?;
     let parsed_response: Result<OpenAICheckSpamResponse, serde_json::Error> =
         serde_json::from_str(&response);
 
     match parsed_response {
-        Ok(response) => {
-            return Ok(response.spam);
-        }
-        Err(error) => {
-            return Err(anyhow::anyhow!(
-                "Failed to parse OpenAI response: {}",
-                error
-            ));
-        }
+        Ok(response) => Ok(response.spam),
+        Err(error) => Err(anyhow::anyhow!(
+            "Failed to parse OpenAI response: {}",
+            error
+        )),
     }
 }
 
@@ -100,11 +116,19 @@ async fn openai_complete_single_message(message: &str, openai_api_key: &str) ->
 
     // Parse the response
     let completion: OpenAICompletionsResponse = response.json().await?;
+    METRICS
+        .llm_tokens
+        .with_label_values(&["prompt"])
+        .inc_by(completion.usage.prompt_tokens as u64);
+    METRICS
+        .llm_tokens
+        .with_label_values(&["completion"])
+        .inc_by(completion.usage.completion_tokens as u64);
 
     // Check if the response has at least one choice
     if completion.choices.is_empty() {
         return Err(anyhow::anyhow!("OpenAI response has no choices"));
     }
 
-    return Ok(completion.choices[0].message.content.clone());
+    Ok(completion.choices[0].message.content.clone())
 }
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use teloxide::{prelude::*, types::ChatMemberKind};
+use tracing::{info, warn};
+
+use crate::{config::Config, metrics::METRICS, retry};
+
+/// Returns whether a chat member has an admin right
+type RightCheck = fn(&ChatMemberKind) -> bool;
+
+/// Admin rights the bot needs to take actions, with the names shown in the
+/// Telegram admin settings
+const REQUIRED_RIGHTS: [(&str, RightCheck); 2] = [
+    ("Delete messages", ChatMemberKind::can_delete_messages),
+    ("Ban users", ChatMemberKind::can_restrict_members),
+];
+
+/// Returns the required rights a chat member lacks
+pub fn missing_rights(member: &ChatMemberKind) -> Vec<&'static str> {
+    REQUIRED_RIGHTS
+        .iter()
+        .filter(|(_, has_right)| !has_right(member))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Returns the rights the bot lacks in a chat
+pub async fn check_chat(bot: &Bot, bot_id: UserId, chat_id: ChatId) -> Result<Vec<&'static str>> {
+    let member = retry::retry(|| bot.get_chat_member(chat_id, bot_id).send()).await?;
+    Ok(missing_rights(&member.kind))
+}
+
+/// Checks the rights of the bot in all chats it knows of at startup and warns
+/// about missing ones
+pub async fn check_known_chats(bot: &Bot, config: &Config, chat_ids: Vec<i64>) {
+    let bot_id = match retry::retry(|| bot.get_me().send()).await {
+        Ok(me) => me.id,
+        Err(error) => {
+            METRICS.record_request_error(&error);
+            warn!(
+                "Failed to get the bot user, skipping the permission check: {}",
+                error
+            );
+            return;
+        }
+    };
+
+    let chat_ids: BTreeSet<i64> = config
+        .chats
+        .keys()
+        .chain(&config.federation.chats)
+        .copied()
+        .chain(chat_ids)
+        .collect();
+    for chat_id in chat_ids {
+        let chat_id = ChatId(chat_id);
+        match check_chat(bot, bot_id, chat_id).await {
+            Ok(missing) if missing.is_empty() => {
+                info!(
+                    chat_id = chat_id.0,
+                    "All required rights are granted in {}", chat_id
+                )
+            }
+            Ok(missing) => warn_missing_rights(bot, chat_id, &missing, config).await,
+            Err(error) => warn!(
+                chat_id = chat_id.0,
+                "Failed to check the rights of the bot in {}: {}", chat_id, error
+            ),
+        }
+    }
+}
+
+/// Warns the admins about rights the bot lacks in a chat, in the log channel
+/// if one is configured and in the chat itself otherwise
+pub async fn warn_missing_rights(bot: &Bot, chat_id: ChatId, missing: &[&str], config: &Config) {
+    warn!(
+        chat_id = chat_id.0,
+        "The bot lacks the rights {} in {}",
+        missing.join(", "),
+        chat_id
+    );
+
+    let (target, text) = match config.log_channel {
+        Some(log_channel) => (
+            ChatId(log_channel),
+            format!(
+                "Aufseher is missing admin rights in chat {}: {}. Actions against spam will fail until they are granted.",
+                chat_id,
+                missing.join(", ")
+            ),
+        ),
+        None => (
+            chat_id,
+            format!(
+                "Aufseher is missing admin rights in this chat: {}. Actions against spam will fail until they are granted.",
+                missing.join(", ")
+            ),
+        ),
+    };
+    if let Err(error) = retry::retry(|| bot.send_message(target, &text).send()).await {
+        METRICS.record_request_error(&error);
+        warn!(
+            "Failed to send the missing rights warning to {}: {}",
+            target, error
+        );
+    }
+}
+
+/// Describes the rights of the bot in a chat for the status command
+pub fn describe_rights(missing: &[&str]) -> String {
+    REQUIRED_RIGHTS
+        .iter()
+        .map(|(name, _)| {
+            let granted = if missing.contains(name) {
+                "missing"
+            }
+            else {
+                "granted"
+            };
+            format!("{}: {}", name, granted)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::ChatMember;
+
+    use crate::permissions;
+
+    #[test]
+    fn test_missing_rights() {
+        let member: ChatMember = serde_json::from_str(
+            r#"{
+                "user": {"id": 1, "is_bot": true, "first_name": "Aufseher"},
+                "status": "administrator",
+                "can_be_edited": false,
+                "is_anonymous": false,
+                "can_manage_chat": true,
+                "can_delete_messages": true,
+                "can_manage_video_chats": false,
+                "can_restrict_members": false,
+                "can_promote_members": false,
+                "can_change_info": false,
+                "can_invite_users": false,
+                "can_post_stories": false,
+                "can_edit_stories": false,
+                "can_delete_stories": false
+            }"#,
+        )
+        .unwrap();
+        let missing = permissions::missing_rights(&member.kind);
+        assert_eq!(missing, vec!["Ban users"]);
+        assert_eq!(
+            permissions::describe_rights(&missing),
+            "Delete messages: granted\nBan users: missing"
+        );
+
+        let member: ChatMember = serde_json::from_str(
+            r#"{"user": {"id": 1, "is_bot": true, "first_name": "Aufseher"}, "status": "member"}"#,
+        )
+        .unwrap();
+        assert_eq!(permissions::missing_rights(&member.kind).len(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    sync::{LazyLock, OnceLock},
+};
+
+use clap::ValueEnum;
+
+// Number of characters kept when truncating
+const TRUNCATE_LENGTH: usize = 8;
+
+static PRIVACY: OnceLock<Privacy> = OnceLock::new();
+
+// Random key of the hashes, so that short names cannot be found by hashing guesses
+static HASH_KEY: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+
+/// How message texts and names appear in logs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Privacy {
+    /// Log texts and names as they are
+    #[default]
+    None,
+    /// Log only the first few characters
+    Truncate,
+    /// Log a hash, which still allows correlating log lines until the bot
+    /// restarts, as it is keyed randomly on startup
+    Hash,
+}
+
+/// Sets the privacy mode for all logs, which can only be done once
+pub fn init(privacy: Privacy) {
+    let _ = PRIVACY.set(privacy);
+}
+
+/// Redacts a message text or name for logging according to the privacy mode
+pub fn redact(text: &str) -> String {
+    redact_with(PRIVACY.get().copied().unwrap_or_default(), text)
+}
+
+/// Returns a message text or name to store in the data directory, which is
+/// only done in full if the privacy mode does not redact texts and names
+pub fn persisted(text: &str) -> Option<String> {
+    (PRIVACY.get().copied().unwrap_or_default() == Privacy::None).then(|| text.to_string())
+}
+
+fn redact_with(privacy: Privacy, text: &str) -> String {
+    match privacy {
+        Privacy::None => text.to_string(),
+        Privacy::Truncate => {
+            let length = text.chars().count();
+            if length <= TRUNCATE_LENGTH {
+                return text.to_string();
+            }
+            let truncated: String = text.chars().take(TRUNCATE_LENGTH).collect();
+            format!("{}… ({} characters)", truncated, length)
+        }
+        Privacy::Hash => format!("#{:016x}", HASH_KEY.hash_one(text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::privacy::{Privacy, redact_with};
+
+    #[test]
+    fn test_redact() {
+        let text = "Earn $500 daily with crypto";
+        assert_eq!(redact_with(Privacy::None, text), text);
+        assert_eq!(
+            redact_with(Privacy::Truncate, text),
+            "Earn $50… (27 characters)"
+        );
+        assert_eq!(redact_with(Privacy::Truncate, "short"), "short");
+
+        // Hashes are stable so log lines about the same text can be correlated
+        let hash = redact_with(Privacy::Hash, text);
+        assert_eq!(hash.len(), 17);
+        assert_eq!(hash, redact_with(Privacy::Hash, text));
+        assert!(!hash.contains("crypto"));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Reports of a message are forgotten after this many seconds
+const REPORT_RETENTION: i64 = 86400;
+
+/// A reported message, appended to the candidate spam corpus for rule authoring
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportedMessage {
+    /// Unix timestamp of the first report
+    pub time: i64,
+    pub chat_id: i64,
+    pub message_id: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<u64>,
+    /// Text of the message, unless `--log-privacy` redacts texts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub reporter_id: u64,
+}
+
+/// Members who reported a message
+#[derive(Default)]
+struct MessageReports {
+    /// Unix timestamp of the first report
+    time: i64,
+    reporters: HashSet<u64>,
+    trusted_reporters: HashSet<u64>,
+}
+
+/// Result of recording a report
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReportCount {
+    /// Whether the message was reported for the first time
+    pub first: bool,
+    pub reporters: usize,
+    pub trusted_reporters: usize,
+}
+
+/// Reports of recent messages by chat ID and message ID
+#[derive(Default)]
+pub struct ReportTracker {
+    messages: Mutex<HashMap<(i64, i32), MessageReports>>,
+}
+
+impl ReportTracker {
+    /// Records a report, returning `None` if the member already reported the message
+    pub fn record(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        reporter_id: u64,
+        trusted: bool,
+        time: i64,
+    ) -> Option<ReportCount> {
+        let mut messages = self.messages.lock().unwrap();
+        messages.retain(|_, reports| time - reports.time < REPORT_RETENTION);
+
+        let first = !messages.contains_key(&(chat_id, message_id));
+        let reports = messages
+            .entry((chat_id, message_id))
+            .or_insert_with(|| MessageReports {
+                time,
+                ..MessageReports::default()
+            });
+        if !reports.reporters.insert(reporter_id) {
+            return None;
+        }
+        if trusted {
+            reports.trusted_reporters.insert(reporter_id);
+        }
+
+        Some(ReportCount {
+            first,
+            reporters: reports.reporters.len(),
+            trusted_reporters: reports.trusted_reporters.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reports::{ReportCount, ReportTracker};
+
+    #[test]
+    fn test_report_tracker() {
+        let tracker = ReportTracker::default();
+        let count = |first, reporters, trusted_reporters| {
+            Some(ReportCount {
+                first,
+                reporters,
+                trusted_reporters,
+            })
+        };
+
+        assert_eq!(tracker.record(-100, 1, 10, true, 0), count(true, 1, 1));
+        // Reporting the same message again does not count
+        assert_eq!(tracker.record(-100, 1, 10, true, 10), None);
+        assert_eq!(tracker.record(-100, 1, 11, false, 20), count(false, 2, 1));
+        assert_eq!(tracker.record(-100, 1, 12, true, 30), count(false, 3, 2));
+        assert_eq!(tracker.record(-200, 1, 10, true, 30), count(true, 1, 1));
+
+        // Old reports are forgotten
+        assert_eq!(tracker.record(-100, 1, 13, true, 86400), count(true, 1, 1));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{fmt, future::Future};
+
+use teloxide::{ApiError, RequestError};
+use tokio::time::{self, Duration};
+use tracing::{debug, warn};
+
+use crate::metrics::METRICS;
+
+// Number of attempts for requests that fail with transient errors
+const MAX_ATTEMPTS: u32 = 4;
+
+// Delay before the second attempt, doubled for every further attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Sends a request, retrying when Telegram asks to slow down or the network fails
+pub async fn retry<T, F, Fut>(request: F) -> Result<T, RequestError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let error = match request().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if attempt >= MAX_ATTEMPTS || is_permanent(&error) {
+            return Err(error);
+        }
+
+        let delay = match &error {
+            RequestError::RetryAfter(seconds) => seconds.duration(),
+            _ => backoff,
+        };
+        debug!(
+            "Request failed (attempt {} of {}), retrying in {:?}: {}",
+            attempt, MAX_ATTEMPTS, delay, error
+        );
+        time::sleep(delay).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+/// Returns whether retrying the request cannot help (e.g., missing rights or
+/// a message that does not exist)
+pub fn is_permanent(error: &RequestError) -> bool {
+    !matches!(
+        error,
+        RequestError::RetryAfter(_) | RequestError::Network(_) | RequestError::Io(_)
+    )
+}
+
+/// A step of an action that failed even after retrying
+#[derive(Debug)]
+pub struct StepFailure {
+    pub step: &'static str,
+    pub error: String,
+    pub permanent: bool,
+}
+
+impl fmt::Display for StepFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = if self.permanent {
+            "permanent"
+        }
+        else {
+            "transient"
+        };
+        write!(f, "{}: {} ({})", self.step, self.error, kind)
+    }
+}
+
+/// Result of an action whose steps are taken independently, so that a failing
+/// step (e.g., deleting a message) does not prevent the others (e.g., the ban)
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Whether the punishment itself (deletion, mute or ban) succeeded
+    pub taken: bool,
+    pub failures: Vec<StepFailure>,
+}
+
+impl Outcome {
+    /// Sends the request of a step with retries, recording the step as failed
+    /// instead of aborting the action
+    pub async fn step<T, F, Fut>(&mut self, step: &'static str, request: F) -> Option<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, RequestError>>,
+    {
+        match retry(request).await {
+            Ok(value) => Some(value),
+            // Someone else deleted the message first
+            Err(RequestError::Api(ApiError::MessageToDeleteNotFound)) => {
+                debug!("Step '{}' skipped: message was already deleted", step);
+                None
+            }
+            Err(error) => {
+                METRICS.record_request_error(&error);
+                self.push_failure(step, error.to_string(), is_permanent(&error));
+                None
+            }
+        }
+    }
+
+    /// Records a step that failed with an error other than a single request error
+    pub fn fail(&mut self, step: &'static str, error: &anyhow::Error) {
+        METRICS.record_error(error);
+        let permanent = error
+            .downcast_ref::<RequestError>()
+            .is_none_or(is_permanent);
+        self.push_failure(step, error.to_string(), permanent);
+    }
+
+    /// Describes the failed steps for the moderation log
+    pub fn failure_descriptions(&self) -> Vec<String> {
+        self.failures
+            .iter()
+            .map(|failure| failure.to_string())
+            .collect()
+    }
+
+    fn push_failure(&mut self, step: &'static str, error: String, permanent: bool) {
+        let failure = StepFailure {
+            step,
+            error,
+            permanent,
+        };
+        warn!("Step failed: {}", failure);
+        self.failures.push(failure);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use teloxide::{ApiError, RequestError, types::Seconds};
+
+    use crate::retry::{self, Outcome};
+
+    #[tokio::test]
+    async fn test_retry() {
+        // Transient errors are retried until the request succeeds
+        let attempts = AtomicU32::new(0);
+        let result = retry::retry(|| async {
+            match attempts.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(RequestError::RetryAfter(Seconds::from_seconds(0))),
+                1 => Err(RequestError::Io(std::io::Error::other("reset").into())),
+                _ => Ok(()),
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        // Permanent errors are not retried and recorded as failed steps
+        let attempts = AtomicU32::new(0);
+        let mut outcome = Outcome::default();
+        let result: Option<()> = outcome
+            .step("ban", || async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(RequestError::Api(ApiError::NotEnoughRightsToRestrict))
+            })
+            .await;
+        assert!(result.is_none());
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+        assert_eq!(outcome.failures.len(), 1);
+        assert!(outcome.failures[0].permanent);
+
+        // Messages that are already gone are not failures
+        let result: Option<()> = outcome
+            .step("delete message", || async {
+                Err(RequestError::Api(ApiError::MessageToDeleteNotFound))
+            })
+            .await;
+        assert!(result.is_none());
+        assert_eq!(outcome.failures.len(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::{Context, Result, bail};
+use teloxide::types::{Message, User};
+
+use crate::{
+    config::{self, Config},
+    document::MessageDocument,
+    explain, matching,
+    rule_store::{RuleChange, RuleKind, StoredRule},
+};
+
+const USAGE: &str = "Usage:
+/aufseher rule add <name|message> <id> <weight> <pattern>
+/aufseher rule remove <id>
+/aufseher rule disable <id>
+/aufseher rule enable <id>
+/aufseher rule list
+/aufseher rule test <name|message> <pattern> (as a reply to test against a message)";
+
+/// Runs a `/aufseher rule` subcommand and returns the response
+pub fn run_rule_command(
+    arguments: &str,
+    message: &Message,
+    user: &User,
+    config: &Config,
+) -> String {
+    let (subcommand, arguments) = split_argument(arguments);
+    let change = |description: String| {
+        RuleChange::new(message.chat.id.0, user.id.0, &user.full_name(), description)
+    };
+
+    let result = match subcommand {
+        "add" => add_rule(arguments, config, change),
+        "remove" => remove_rule(arguments, config, change),
+        "disable" => set_rule_disabled(arguments, true, config, change),
+        "enable" => set_rule_disabled(arguments, false, config, change),
+        "list" => Ok(list_rules(config)),
+        "test" => test_rule(arguments, message.reply_to_message(), config),
+        _ => Ok(USAGE.to_string()),
+    };
+    result.unwrap_or_else(|error| format!("{:#}", error))
+}
+
+fn add_rule(
+    arguments: &str,
+    config: &Config,
+    change: impl Fn(String) -> RuleChange,
+) -> Result<String> {
+    let (kind, arguments) = split_argument(arguments);
+    let (id, arguments) = split_argument(arguments);
+    let (weight, pattern) = split_argument(arguments);
+    if pattern.is_empty() {
+        bail!(USAGE);
+    }
+    let kind: RuleKind = kind.parse()?;
+    let weight = parse_weight(weight)?;
+    if config.has_rule(id) {
+        bail!("A rule with the ID '{}' is defined in the config files", id);
+    }
+
+    let description = format!(
+        "added {} rule '{}' with weight {}: {}",
+        kind, id, weight, pattern
+    );
+    let rule = StoredRule {
+        id: id.to_string(),
+        kind,
+        pattern: pattern.to_string(),
+        weight,
+    };
+    config
+        .rule_store
+        .add(rule, change(description))
+        .context("Failed to add the rule")?;
+    Ok(format!("Rule '{}' has been added.", id))
+}
+
+fn remove_rule(
+    arguments: &str,
+    config: &Config,
+    change: impl Fn(String) -> RuleChange,
+) -> Result<String> {
+    let id = arguments;
+    if config.has_rule(id) && !config.rule_store.contains(id) {
+        bail!(
+            "Rule '{}' is defined in the config files and can only be disabled",
+            id
+        );
+    }
+
+    config
+        .rule_store
+        .remove(id, change(format!("removed rule '{}'", id)))
+        .context("Failed to remove the rule")?;
+    Ok(format!("Rule '{}' has been removed.", id))
+}
+
+fn set_rule_disabled(
+    arguments: &str,
+    disabled: bool,
+    config: &Config,
+    change: impl Fn(String) -> RuleChange,
+) -> Result<String> {
+    let id = arguments;
+    if !config.has_rule(id) && !config.rule_store.contains(id) {
+        bail!("Unknown rule '{}'", id);
+    }
+
+    let state = if disabled { "disabled" } else { "enabled" };
+    config
+        .rule_store
+        .set_disabled(id, disabled, change(format!("{} rule '{}'", state, id)))?;
+    Ok(format!("Rule '{}' has been {}.", id, state))
+}
+
+fn list_rules(config: &Config) -> String {
+    let (rules, disabled) = config.rule_store.list();
+    let mut lines = Vec::new();
+    if rules.is_empty() {
+        lines.push("No rules have been added with commands.".to_string());
+    }
+    else {
+        lines.push("Rules added with commands:".to_string());
+        for rule in rules {
+            lines.push(format!(
+                "- {} ({}, weight {}): {}",
+                rule.id, rule.kind, rule.weight, rule.pattern
+            ));
+        }
+    }
+    if !disabled.is_empty() {
+        lines.push(format!("Disabled rules: {}", disabled.join(", ")));
+    }
+    explain::truncate_report(lines.join("\n"))
+}
+
+/// Checks that a pattern compiles and reports whether it matches the target message
+fn test_rule(arguments: &str, target: Option<&Message>, config: &Config) -> Result<String> {
+    let (kind, pattern) = split_argument(arguments);
+    if pattern.is_empty() {
+        bail!(USAGE);
+    }
+    let kind: RuleKind = kind.parse()?;
+    let rules = config.rule_store.compile_rule(kind, pattern)?;
+
+    let Some(target) = target
+    else {
+        return Ok(
+            "The pattern is valid. Reply to a message to test it against the message.".to_string(),
+        );
+    };
+
+    let mut inputs = Vec::new();
+    match kind {
+        RuleKind::Name => {
+            if let Some(user) = &target.from {
+                inputs.push(("sender name".to_string(), user.full_name()));
+            }
+        }
+        RuleKind::Message => {
+            for field in MessageDocument::from_message(target).fields {
+                let deobfuscated =
+                    matching::deobfuscate_message_text(&field.text, &config.deobfuscation);
+                inputs.push((format!("message {}", field.name), field.text));
+                inputs.push((format!("deobfuscated message {}", field.name), deobfuscated));
+            }
+        }
+    }
+    if inputs.is_empty() {
+        return Ok("The pattern is valid, but the message has nothing to match.".to_string());
+    }
+
+    let mut lines = vec!["The pattern is valid.".to_string()];
+    for (source, input) in inputs {
+        let matched = !rules.matches(&input)?.is_empty();
+        lines.push(format!(
+            "{}: {}",
+            source,
+            if matched { "match" } else { "no match" }
+        ));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Parses a rule weight, rejecting NaN and infinite weights that would break
+/// the score of every message the rule matches
+fn parse_weight(weight: &str) -> Result<f64> {
+    match weight.parse::<f64>() {
+        Ok(parsed) if config::check_weight(parsed, "the rule").is_ok() => Ok(parsed),
+        _ => bail!("Invalid weight '{}'", weight),
+    }
+}
+
+/// Splits off the first whitespace-separated argument
+fn split_argument(arguments: &str) -> (&str, &str) {
+    match arguments.split_once(char::is_whitespace) {
+        Some((argument, rest)) => (argument, rest.trim_start()),
+        None => (arguments, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rule_commands::parse_weight;
+
+    #[test]
+    fn test_parse_weight() {
+        assert_eq!(parse_weight("0.5").unwrap(), 0.5);
+        assert_eq!(parse_weight("-1").unwrap(), -1.0);
+        for weight in ["NaN", "inf", "-inf", "heavy"] {
+            let error = parse_weight(weight).err().unwrap();
+            assert_eq!(error.to_string(), format!("Invalid weight '{}'", weight));
+        }
+    }
+}
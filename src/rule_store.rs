@@ -0,0 +1,353 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::{Result, bail};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    config::{Config, RegexLimitsConfig, RuleConfig},
+    matching::{Rule, RuleSet},
+    privacy,
+    state::{load_json, save_json},
+};
+
+pub const RULES_FILE: &str = "rules.json";
+
+/// Whether a rule matches names or message texts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    Name,
+    Message,
+}
+
+impl fmt::Display for RuleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleKind::Name => write!(f, "name"),
+            RuleKind::Message => write!(f, "message"),
+        }
+    }
+}
+
+impl FromStr for RuleKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "name" => Ok(RuleKind::Name),
+            "message" => Ok(RuleKind::Message),
+            _ => bail!(
+                "Unknown rule kind '{}' (expected 'name' or 'message')",
+                kind
+            ),
+        }
+    }
+}
+
+/// A rule added with an admin command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredRule {
+    pub id: String,
+    pub kind: RuleKind,
+    pub pattern: String,
+    pub weight: f64,
+}
+
+/// A change to the rule store, kept as an audit trail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleChange {
+    /// Unix timestamp of the change
+    pub time: i64,
+    pub chat_id: i64,
+    pub user_id: u64,
+    /// Name of the admin at the time of the change, redacted according to `--log-privacy`
+    pub user: String,
+    pub change: String,
+}
+
+impl RuleChange {
+    pub fn new(chat_id: i64, user_id: u64, user: &str, change: String) -> Self {
+        Self {
+            time: Utc::now().timestamp(),
+            chat_id,
+            user_id,
+            user: privacy::redact(user),
+            change,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct StoredRules {
+    #[serde(default)]
+    rules: Vec<StoredRule>,
+    /// IDs of rules and keyword lists of the config file or the store that are disabled
+    #[serde(default)]
+    disabled: BTreeSet<String>,
+    #[serde(default)]
+    audit: Vec<RuleChange>,
+}
+
+/// The compiled rules of the store together with the disabled rule IDs
+pub struct ActiveRules {
+    name: RuleSet,
+    message: RuleSet,
+    disabled: BTreeSet<String>,
+}
+
+impl ActiveRules {
+    fn compile(stored: &StoredRules, limits: &RegexLimitsConfig) -> Result<ActiveRules> {
+        let rule_configs = |kind: RuleKind| -> Vec<RuleConfig> {
+            stored
+                .rules
+                .iter()
+                .filter(|rule| rule.kind == kind)
+                .map(|rule| RuleConfig {
+                    pattern: rule.pattern.clone(),
+                    id: Some(rule.id.clone()),
+                    weight: rule.weight,
+                    description: None,
+                })
+                .collect()
+        };
+
+        Ok(ActiveRules {
+            name: RuleSet::new("name", &rule_configs(RuleKind::Name), limits)?,
+            message: RuleSet::new("message", &rule_configs(RuleKind::Message), limits)?,
+            disabled: stored.disabled.clone(),
+        })
+    }
+
+    /// Returns whether a rule or keyword list is disabled
+    pub fn is_disabled(&self, id: &str) -> bool {
+        self.disabled.contains(id)
+    }
+
+    /// Returns the enabled rules of the config file and the store that match
+    /// the input, rules of the config file first
+    pub fn find_matches<'a>(
+        &'a self,
+        input: &str,
+        kind: RuleKind,
+        config: &'a Config,
+    ) -> Result<Vec<&'a Rule>, fancy_regex::Error> {
+        let (config_rules, stored_rules) = match kind {
+            RuleKind::Name => (&config.name_regexes, &self.name),
+            RuleKind::Message => (&config.message_regexes, &self.message),
+        };
+
+        let mut matched = config_rules.matches(input)?;
+        matched.extend(stored_rules.matches(input)?);
+        matched.retain(|rule| !self.is_disabled(&rule.id));
+        Ok(matched)
+    }
+}
+
+/// Rules managed with admin commands, layered on top of the rules of the config file
+///
+/// The rules are kept in `rules.json` in the data directory together with an
+/// audit trail of all changes. Without a data directory, changes are lost
+/// when the bot restarts.
+pub struct RuleStore {
+    data_dir: Option<PathBuf>,
+    limits: RegexLimitsConfig,
+    stored: Mutex<StoredRules>,
+    active: RwLock<Arc<ActiveRules>>,
+}
+
+impl RuleStore {
+    pub fn load(data_dir: Option<&Path>, limits: &RegexLimitsConfig) -> Result<RuleStore> {
+        let stored: StoredRules = match data_dir {
+            Some(data_dir) => load_json(data_dir, RULES_FILE)?,
+            None => StoredRules::default(),
+        };
+        if !stored.rules.is_empty() || !stored.disabled.is_empty() {
+            info!(
+                "Loaded {} rules added by admins and {} disabled rules",
+                stored.rules.len(),
+                stored.disabled.len()
+            );
+        }
+
+        Ok(RuleStore {
+            data_dir: data_dir.map(Path::to_path_buf),
+            limits: limits.clone(),
+            active: RwLock::new(Arc::new(ActiveRules::compile(&stored, limits)?)),
+            stored: Mutex::new(stored),
+        })
+    }
+
+    /// Returns the rules currently in effect
+    pub fn active(&self) -> Arc<ActiveRules> {
+        self.active.read().unwrap().clone()
+    }
+
+    /// Returns the stored rules and the disabled rule IDs
+    pub fn list(&self) -> (Vec<StoredRule>, Vec<String>) {
+        let stored = self.stored.lock().unwrap();
+        (
+            stored.rules.clone(),
+            stored.disabled.iter().cloned().collect(),
+        )
+    }
+
+    /// Returns whether a rule with the ID was added to the store
+    pub fn contains(&self, id: &str) -> bool {
+        let stored = self.stored.lock().unwrap();
+        stored.rules.iter().any(|rule| rule.id == id)
+    }
+
+    /// Compiles a single pattern with the configured limits without adding it
+    pub fn compile_rule(&self, kind: RuleKind, pattern: &str) -> Result<RuleSet> {
+        let rule_config = RuleConfig {
+            pattern: pattern.to_string(),
+            id: None,
+            weight: 1.0,
+            description: None,
+        };
+        Ok(RuleSet::new(
+            &kind.to_string(),
+            &[rule_config],
+            &self.limits,
+        )?)
+    }
+
+    /// Adds a rule, failing if the ID is taken or the pattern does not compile
+    pub fn add(&self, rule: StoredRule, change: RuleChange) -> Result<()> {
+        self.update(change, |stored| {
+            if stored
+                .rules
+                .iter()
+                .any(|stored_rule| stored_rule.id == rule.id)
+            {
+                bail!("A rule with the ID '{}' already exists", rule.id);
+            }
+            stored.rules.push(rule);
+            Ok(())
+        })
+    }
+
+    /// Removes a rule added to the store
+    pub fn remove(&self, id: &str, change: RuleChange) -> Result<()> {
+        self.update(change, |stored| {
+            let count = stored.rules.len();
+            stored.rules.retain(|rule| rule.id != id);
+            if stored.rules.len() == count {
+                bail!("No rule with the ID '{}' was added with a command", id);
+            }
+            stored.disabled.remove(id);
+            Ok(())
+        })
+    }
+
+    /// Disables or enables a rule or keyword list
+    pub fn set_disabled(&self, id: &str, disabled: bool, change: RuleChange) -> Result<()> {
+        self.update(change, |stored| {
+            let changed = if disabled {
+                stored.disabled.insert(id.to_string())
+            }
+            else {
+                stored.disabled.remove(id)
+            };
+            if !changed {
+                let state = if disabled { "disabled" } else { "enabled" };
+                bail!("Rule '{}' is already {}", id, state);
+            }
+            Ok(())
+        })
+    }
+
+    /// Applies a change to a copy of the stored rules and only keeps it if the
+    /// rules still compile and could be saved
+    fn update(
+        &self,
+        change: RuleChange,
+        apply: impl FnOnce(&mut StoredRules) -> Result<()>,
+    ) -> Result<()> {
+        let mut stored = self.stored.lock().unwrap();
+        let mut updated = stored.clone();
+        apply(&mut updated)?;
+        let active = ActiveRules::compile(&updated, &self.limits)?;
+
+        info!(
+            chat_id = change.chat_id,
+            user_id = change.user_id,
+            "Rules changed by '{}' ({}): {}",
+            change.user,
+            change.user_id,
+            change.change
+        );
+        updated.audit.push(change);
+        if let Some(data_dir) = &self.data_dir {
+            save_json(data_dir, RULES_FILE, &updated)?;
+        }
+
+        *stored = updated;
+        *self.active.write().unwrap() = Arc::new(active);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        config::RegexLimitsConfig,
+        rule_store::{RuleChange, RuleKind, RuleStore, StoredRule},
+        testing::TempDir,
+    };
+
+    #[test]
+    fn test_rule_store() {
+        let dir = TempDir::new("rules");
+        let data_dir = dir.path();
+        let limits = RegexLimitsConfig::default();
+        let change = |text: &str| RuleChange::new(-100, 1, "Admin", text.to_string());
+
+        let store = RuleStore::load(Some(data_dir), &limits).unwrap();
+        let rule = StoredRule {
+            id: "casino".to_string(),
+            kind: RuleKind::Message,
+            pattern: "(?i)casino".to_string(),
+            weight: 1.0,
+        };
+        store.add(rule.clone(), change("add casino")).unwrap();
+        assert!(store.add(rule, change("add casino again")).is_err());
+
+        // Invalid patterns are rejected without changing the store
+        let invalid = StoredRule {
+            id: "invalid".to_string(),
+            kind: RuleKind::Name,
+            pattern: "(unclosed".to_string(),
+            weight: 1.0,
+        };
+        assert!(store.add(invalid, change("add invalid")).is_err());
+        assert!(!store.contains("invalid"));
+
+        store
+            .set_disabled("message-1", true, change("disable message-1"))
+            .unwrap();
+        assert!(store.active().is_disabled("message-1"));
+
+        // Rules, disabled rules and the audit trail survive a restart
+        let store = RuleStore::load(Some(data_dir), &limits).unwrap();
+        let (rules, disabled) = store.list();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(disabled, ["message-1"]);
+        let contents = fs::read_to_string(data_dir.join("rules.json")).unwrap();
+        assert!(contents.contains("disable message-1"));
+
+        store.remove("casino", change("remove casino")).unwrap();
+        assert!(store.remove("casino", change("remove casino")).is_err());
+        assert!(!store.contains("casino"));
+    }
+}
@@ -0,0 +1,470 @@
+use anyhow::Result;
+use teloxide::types::{Chat, MediaKind, Message, MessageKind, MessageOrigin, User};
+
+use crate::{
+    config::Config,
+    document::{DocumentField, FieldKind, MessageDocument},
+    keywords,
+    matching::{self, Rule},
+    rule_store::RuleKind,
+};
+
+// Sources of signals about the content of the sender themselves, which the
+// moderation log keeps as their name and message text
+const OWN_NAME_SOURCES: &[&str] = &["sender name", "member name"];
+const OWN_TEXT_SOURCES: &[&str] = &[
+    "message text",
+    "message caption",
+    "deobfuscated message text",
+    "deobfuscated message caption",
+];
+
+/// A single contribution to the spam score of a message
+pub struct Signal {
+    /// Where the signal came from (e.g., `message text`, `sender name` or `llm`)
+    pub source: String,
+    /// ID of the rule that produced the signal, if any
+    pub rule_id: Option<String>,
+    /// Kind of the regex rule that produced the signal, if any (keyword lists have none)
+    pub rule_kind: Option<RuleKind>,
+    /// Description of the rule that produced the signal, if any
+    pub description: Option<String>,
+    pub weight: f64,
+}
+
+/// Sum of the weights of all rules and signals that apply to a message
+#[derive(Default)]
+pub struct Score {
+    pub signals: Vec<Signal>,
+}
+
+impl Score {
+    pub fn total(&self) -> f64 {
+        self.signals.iter().map(|signal| signal.weight).sum()
+    }
+
+    /// Describes the signal with the highest weight, preferring the rule
+    /// description over the rule ID over the signal source
+    pub fn reason(&self) -> String {
+        self.signals
+            .iter()
+            .max_by(|a, b| a.weight.total_cmp(&b.weight))
+            .map(|signal| {
+                signal
+                    .description
+                    .clone()
+                    .or_else(|| signal.rule_id.clone())
+                    .unwrap_or_else(|| signal.source.clone())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds a signal that is not produced by a rule, ignoring zero weights
+    pub fn add_signal(&mut self, source: &str, weight: f64) {
+        if weight == 0.0 {
+            return;
+        }
+
+        self.signals.push(Signal {
+            source: source.to_string(),
+            rule_id: None,
+            rule_kind: None,
+            description: None,
+            weight,
+        });
+    }
+
+    /// Returns the kinds of regex rules that matched the name of the sender or
+    /// the text or caption of their message
+    ///
+    /// These are the name and text kept in the moderation log, so only they
+    /// can be exported as samples the rules are known to match.
+    pub fn own_rule_kinds(&self) -> Vec<RuleKind> {
+        let mut kinds = Vec::new();
+        for signal in &self.signals {
+            let own_sources = match signal.rule_kind {
+                Some(RuleKind::Name) => OWN_NAME_SOURCES,
+                Some(RuleKind::Message) => OWN_TEXT_SOURCES,
+                None => continue,
+            };
+            if own_sources.contains(&signal.source.as_str())
+                && let Some(kind) = signal.rule_kind
+                && !kinds.contains(&kind)
+            {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
+    /// Scores all fields of the message document on their own and combined
+    ///
+    /// The sender's own fields are scored first, so rules that match them
+    /// count fully. Rules that only match with the quoted fields are weighted
+    /// by `signals.quoted_content`, so quoting spam does not count as sending it.
+    pub fn add_document(&mut self, document: &MessageDocument, config: &Config) -> Result<()> {
+        let own_fields: Vec<&DocumentField> = document.fields_of(FieldKind::Own).collect();
+        for field in &own_fields {
+            self.add_text(field.name, &field.text, config)?;
+        }
+        if own_fields.len() > 1 {
+            self.add_text("combined", &document.own_text(), config)?;
+        }
+
+        let factor = config.signals.quoted_content;
+        if factor == 0.0 || own_fields.len() == document.fields.len() {
+            return Ok(());
+        }
+        for field in document.fields_of(FieldKind::Quoted) {
+            self.add_weighted_text(field.name, &field.text, factor, config)?;
+        }
+        self.add_weighted_text(
+            "combined with quotes",
+            &document.combined_text(),
+            factor,
+            config,
+        )
+    }
+
+    /// Scores a text against the message rules, both as is and deobfuscated
+    pub fn add_text(&mut self, field: &str, text: &str, config: &Config) -> Result<()> {
+        self.add_weighted_text(field, text, 1.0, config)
+    }
+
+    /// Scores a text against the message rules with their weights multiplied by a factor
+    fn add_weighted_text(
+        &mut self,
+        field: &str,
+        text: &str,
+        factor: f64,
+        config: &Config,
+    ) -> Result<()> {
+        let rules = config.rule_store.active();
+        for rule in rules.find_matches(text, RuleKind::Message, config)? {
+            self.add_match(
+                &format!("message {}", field),
+                &rule.id,
+                Some(RuleKind::Message),
+                rule.description.as_deref(),
+                rule.weight * factor,
+            );
+        }
+
+        let deobfuscated_text = matching::deobfuscate_message_text(text, &config.deobfuscation);
+        for rule in rules.find_matches(&deobfuscated_text, RuleKind::Message, config)? {
+            self.add_match(
+                &format!("deobfuscated message {}", field),
+                &rule.id,
+                Some(RuleKind::Message),
+                rule.description.as_deref(),
+                rule.weight * factor,
+            );
+        }
+
+        let deobfuscated_words = matching::deobfuscate_words(text, &config.deobfuscation);
+        for (list, keyword) in keywords::find_matches(text, &deobfuscated_words, &config.keywords)
+            .into_iter()
+            .filter(|(list, _)| !rules.is_disabled(&list.id))
+        {
+            self.add_match(
+                &format!("message {} keyword '{}'", field, keyword),
+                &list.id,
+                None,
+                list.description.as_deref(),
+                list.weight * factor,
+            );
+        }
+        Ok(())
+    }
+
+    /// Scores the names of the sender, forwarder and inline bot of a message
+    pub fn add_message_names(&mut self, message: &Message, config: &Config) -> Result<()> {
+        if let Some(user) = &message.from {
+            self.add_name("sender name", &user.full_name(), config)?;
+        }
+
+        // Users posting on behalf of a channel or group, and their signatures
+        if let Some(sender_chat) = &message.sender_chat {
+            self.add_chat("sender chat", sender_chat, config)?;
+        }
+        if let Some(author_signature) = message.author_signature() {
+            self.add_name("author signature", author_signature, config)?;
+        }
+
+        if let Some(forward_origin) = message.forward_origin() {
+            self.add_origin("forwarder", forward_origin, config)?;
+        }
+
+        // Forwarded stories
+        if let MessageKind::Common(message_common) = &message.kind
+            && let MediaKind::Story(media_story) = &message_common.media_kind
+        {
+            self.add_forwarded_chat("story chat", &media_story.story.chat, config)?;
+        }
+
+        if let Some(via_bot) = &message.via_bot {
+            self.add_name("via bot name", &via_bot.full_name(), config)?;
+        }
+        Ok(())
+    }
+
+    /// Scores the names and signature of the origin of a forwarded message
+    fn add_origin(&mut self, source: &str, origin: &MessageOrigin, config: &Config) -> Result<()> {
+        match origin {
+            MessageOrigin::User {
+                sender_user,
+                ..
+            } => self.add_name(
+                &format!("{} name", source),
+                &sender_user.full_name(),
+                config,
+            )?,
+            MessageOrigin::HiddenUser {
+                sender_user_name,
+                ..
+            } => self.add_name(&format!("{} name", source), sender_user_name, config)?,
+            MessageOrigin::Chat {
+                sender_chat: chat,
+                author_signature,
+                ..
+            }
+            | MessageOrigin::Channel {
+                chat,
+                author_signature,
+                ..
+            } => {
+                self.add_forwarded_chat(&format!("{} chat", source), chat, config)?;
+                if let Some(author_signature) = author_signature {
+                    self.add_name(&format!("{} signature", source), author_signature, config)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Scores a chat content was forwarded from, which may be blocked entirely
+    fn add_forwarded_chat(&mut self, source: &str, chat: &Chat, config: &Config) -> Result<()> {
+        if config.blocked_forward_chats.contains(&chat.id.0) {
+            self.add_signal(
+                &format!("{} {} is blocked", source, chat.id),
+                config.signals.blocked_forward,
+            );
+        }
+        self.add_chat(source, chat, config)
+    }
+
+    /// Scores the title and username of a chat against the name rules
+    fn add_chat(&mut self, source: &str, chat: &Chat, config: &Config) -> Result<()> {
+        if let Some(title) = chat.title() {
+            self.add_name(&format!("{} name", source), title, config)?;
+        }
+        if let Some(username) = chat.username() {
+            self.add_name(&format!("{} username", source), username, config)?;
+        }
+        Ok(())
+    }
+
+    /// Scores a name against the name rules
+    pub fn add_name(&mut self, source: &str, name: &str, config: &Config) -> Result<()> {
+        let rules = config.rule_store.active();
+        for rule in rules.find_matches(name, RuleKind::Name, config)? {
+            self.add_rule(source, RuleKind::Name, rule);
+        }
+        Ok(())
+    }
+
+    /// Scores how much the user account can be trusted
+    pub fn add_user(&mut self, user: &User, config: &Config) {
+        if user.username.is_none() {
+            self.add_signal("no username", config.signals.no_username);
+        }
+        if user.is_premium {
+            self.add_signal("premium user", config.signals.premium);
+        }
+        if user.id.0 >= config.signals.new_account_min_id {
+            self.add_signal("new account", config.signals.new_account);
+        }
+    }
+
+    fn add_rule(&mut self, source: &str, kind: RuleKind, rule: &Rule) {
+        self.add_match(
+            source,
+            &rule.id,
+            Some(kind),
+            rule.description.as_deref(),
+            rule.weight,
+        );
+    }
+
+    /// Adds the weight of a rule or keyword list unless it already matched another field
+    fn add_match(
+        &mut self,
+        source: &str,
+        rule_id: &str,
+        rule_kind: Option<RuleKind>,
+        description: Option<&str>,
+        weight: f64,
+    ) {
+        if self
+            .signals
+            .iter()
+            .any(|signal| signal.rule_id.as_deref() == Some(rule_id))
+        {
+            return;
+        }
+
+        self.signals.push(Signal {
+            source: source.to_string(),
+            rule_id: Some(rule_id.to_string()),
+            rule_kind,
+            description: description.map(str::to_string),
+            weight,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::Message;
+
+    use crate::{
+        actions::Action, config::Config, document::MessageDocument, rule_store::RuleKind,
+        scoring::Score,
+    };
+
+    #[test]
+    fn test_message_names() {
+        let config = Config::from_yaml_str(
+            "name_regexes: ['(?i)free_?crypto', 'Hidden Spammer']\nblocked_forward_chats: [-1001]\n",
+        );
+
+        let message = |forward_origin: &str| -> Message {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "message_id": 1,
+                    "date": 0,
+                    "chat": {{"id": -100, "type": "supergroup", "title": "Group"}},
+                    "from": {{"id": 1, "is_bot": false, "first_name": "Alice"}},
+                    "forward_origin": {},
+                    "text": "hello"
+                }}"#,
+                forward_origin
+            ))
+            .unwrap()
+        };
+        let sources = |message: &Message| {
+            let mut score = Score::default();
+            score.add_message_names(message, &config).unwrap();
+            score
+                .signals
+                .into_iter()
+                .map(|signal| signal.source)
+                .collect::<Vec<String>>()
+        };
+
+        // Channel usernames and signatures of forwarded channel posts are checked
+        let channel = message(
+            r#"{"type": "channel", "date": 0, "message_id": 5, "author_signature": "Hidden Spammer",
+                "chat": {"id": -1001, "type": "channel", "title": "News", "username": "FreeCrypto"}}"#,
+        );
+        assert_eq!(
+            sources(&channel),
+            [
+                "forwarder chat -1001 is blocked",
+                "forwarder chat username",
+                "forwarder signature"
+            ]
+        );
+
+        // Names of hidden users are checked
+        let hidden_user =
+            message(r#"{"type": "hidden_user", "date": 0, "sender_user_name": "Hidden Spammer"}"#);
+        assert_eq!(sources(&hidden_user), ["forwarder name"]);
+    }
+
+    #[test]
+    fn test_own_rule_kinds() {
+        let config = Config::from_yaml_str(
+            "name_regexes: ['(?i)free_?crypto']\nmessage_regexes: ['(?i)casino']\n",
+        );
+
+        // Rules matching forwarded names or the text of other fields are not
+        // about the name and text kept in the moderation log
+        let mut score = Score::default();
+        score
+            .add_name("forwarder name", "Free Crypto", &config)
+            .unwrap();
+        score.add_text("quote", "casino", &config).unwrap();
+        score.add_signal("llm", 1.0);
+        assert!(score.own_rule_kinds().is_empty());
+
+        let mut score = Score::default();
+        score
+            .add_name("sender name", "FreeCrypto", &config)
+            .unwrap();
+        score.add_text("text", "Casino", &config).unwrap();
+        assert_eq!(score.own_rule_kinds(), [RuleKind::Name, RuleKind::Message]);
+    }
+
+    #[test]
+    fn test_quoted_content() {
+        let quoting = |text: &str| -> Message {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "message_id": 2,
+                    "date": 0,
+                    "chat": {{"id": -100, "type": "supergroup", "title": "Group"}},
+                    "from": {{"id": 1, "is_bot": false, "first_name": "Alice"}},
+                    "text": "{}",
+                    "quote": {{"text": "Best casino bonus", "position": 0}},
+                    "external_reply": {{
+                        "origin": {{"type": "channel", "date": 0, "message_id": 7,
+                            "chat": {{"id": -200, "type": "channel", "title": "Casino Bonus"}}}},
+                        "message_id": 7,
+                        "link_preview_options": {{"is_disabled": false, "url": "https://casino.example/"}},
+                        "photo": [{{"file_id": "a", "file_unique_id": "b", "width": 1, "height": 1}}]
+                    }}
+                }}"#,
+                text
+            ))
+            .unwrap()
+        };
+        let action = |yaml: &str, message: &Message| -> Action {
+            let config = Config::from_yaml_str(yaml);
+            let mut score = Score::default();
+            score
+                .add_document(&MessageDocument::from_message(message), &config)
+                .unwrap();
+            config
+                .thresholds_for(message.chat.id)
+                .action_for(score.total())
+        };
+        assert_eq!(
+            MessageDocument::from_message(&quoting("Look")).fields.len(),
+            4
+        );
+        let rules = "message_regexes: ['(?i)casino', '(?i)scam\\W+best casino']\n";
+
+        // Quoting spam to call it out does not act on the quoting user
+        let calling_out = quoting("This is a scam");
+        assert_eq!(action(rules, &calling_out), Action::None);
+
+        // Unless quoted content is opted into, and then with less weight
+        let opted_in = format!("{}signals: {{quoted_content: 0.5}}\n", rules);
+        let config = Config::from_yaml_str(&opted_in);
+        let mut score = Score::default();
+        score
+            .add_document(&MessageDocument::from_message(&calling_out), &config)
+            .unwrap();
+        assert_eq!(score.total(), 1.0);
+        assert!(
+            score
+                .signals
+                .iter()
+                .any(|signal| signal.source == "message combined with quotes")
+        );
+
+        // Spam in the sender's own text still counts fully
+        assert_eq!(action(rules, &quoting("Join my casino")), Action::Ban);
+    }
+}
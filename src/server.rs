@@ -0,0 +1,103 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use tracing::{error, info};
+
+use crate::{metrics::METRICS, state::State as BotState};
+
+/// Starts the HTTP server exposing `/metrics`, `/healthz` and `/readyz` in the
+/// background and returns the address it listens on
+pub async fn serve(address: SocketAddr, state: Arc<BotState>) -> Result<SocketAddr> {
+    let router = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to listen on {}", address))?;
+    let address = listener
+        .local_addr()
+        .context("Failed to get the address of the HTTP server")?;
+
+    info!("Serving metrics and health checks on http://{}", address);
+    tokio::spawn(async move {
+        if let Err(error) = axum::serve(listener, router).await {
+            error!("HTTP server failed: {}", error);
+        }
+    });
+    Ok(address)
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.encode(),
+    )
+}
+
+// Alive as long as polling works
+async fn healthz_handler(State(state): State<Arc<BotState>>) -> impl IntoResponse {
+    match state.health.check_polling() {
+        None => (StatusCode::OK, "ok".to_string()),
+        Some(problem) => (StatusCode::SERVICE_UNAVAILABLE, problem),
+    }
+}
+
+// Ready if polling works, the Telegram API is reachable, the config is loaded and the storage is reachable
+async fn readyz_handler(State(state): State<Arc<BotState>>) -> impl IntoResponse {
+    let problems = state.health.check_readiness();
+    if problems.is_empty() {
+        (StatusCode::OK, "ok".to_string())
+    }
+    else {
+        (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{server, state::State};
+
+    #[tokio::test]
+    async fn test_health_endpoints() {
+        let state = Arc::new(State::default());
+        // Let the system choose a free port
+        let address = server::serve("127.0.0.1:0".parse().unwrap(), state.clone())
+            .await
+            .unwrap();
+
+        let status = |path: &'static str| async move {
+            reqwest::get(format!("http://{}{}", address, path))
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        };
+
+        // Not ready until the config is loaded and the Telegram API was reached
+        assert_eq!(status("/healthz").await, 200);
+        assert_eq!(status("/readyz").await, 503);
+
+        state.health.set_config_loaded();
+        state.health.record_heartbeat();
+        assert_eq!(status("/healthz").await, 200);
+        assert_eq!(status("/readyz").await, 200);
+        assert_eq!(status("/metrics").await, 200);
+
+        // A successful heartbeat does not hide failed polling
+        state.health.record_poll_error();
+        state.health.record_heartbeat();
+        assert_eq!(status("/healthz").await, 503);
+        assert_eq!(status("/readyz").await, 503);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup};
+
+use crate::{
+    actions::Action,
+    config::{Config, ServiceMessagesConfig},
+};
+
+/// Prefix of the callback data of the settings menu buttons
+pub const CALLBACK_PREFIX: &str = "settings:";
+
+/// A setting that chat admins can change in the settings menu
+///
+/// The bot has no captcha for new members, so there is no setting for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Llm,
+    ShadowMode,
+    Notices,
+    ServiceMessages,
+    MaxAction,
+}
+
+impl Setting {
+    const ALL: [Setting; 5] = [
+        Setting::Llm,
+        Setting::ShadowMode,
+        Setting::Notices,
+        Setting::ServiceMessages,
+        Setting::MaxAction,
+    ];
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Setting::Llm => "llm",
+            Setting::ShadowMode => "shadow_mode",
+            Setting::Notices => "notices",
+            Setting::ServiceMessages => "service_messages",
+            Setting::MaxAction => "max_action",
+        }
+    }
+
+    /// Returns the setting of the callback data of a settings menu button
+    pub fn from_callback_data(data: &str) -> Option<Setting> {
+        let key = data.strip_prefix(CALLBACK_PREFIX)?;
+        Setting::ALL
+            .into_iter()
+            .find(|setting| setting.key() == key)
+    }
+}
+
+/// Settings of a chat changed by its admins, overriding the config file
+///
+/// Settings that were never changed fall back to the config file.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Check messages with the LLM if an API key is configured
+    pub llm: Option<bool>,
+    /// Log the actions that would be taken instead of taking them
+    pub shadow_mode: bool,
+    pub notices: Option<bool>,
+    /// Delete all service messages (or none)
+    pub service_messages: Option<bool>,
+    /// Most severe action taken in the chat, including flood mutes and
+    /// federated and duplicate bans
+    pub max_action: Option<Action>,
+}
+
+impl ChatSettings {
+    pub fn llm_enabled(&self) -> bool {
+        self.llm.unwrap_or(true)
+    }
+
+    pub fn notices_enabled(&self, chat_id: ChatId, config: &Config) -> bool {
+        self.notices
+            .unwrap_or_else(|| config.notices_for(chat_id).enabled)
+    }
+
+    /// Returns the service messages deleted in the chat
+    pub fn service_messages(&self, chat_id: ChatId, config: &Config) -> ServiceMessagesConfig {
+        match self.service_messages {
+            Some(delete) => ServiceMessagesConfig {
+                joins: delete,
+                leaves: delete,
+                pinned: delete,
+                title_changes: delete,
+            },
+            None => config.service_messages_for(chat_id).clone(),
+        }
+    }
+
+    /// Limits an action to the most severe action allowed in the chat
+    pub fn limit_action(&self, action: Action) -> Action {
+        action.min(self.max_action.unwrap_or(Action::Ban))
+    }
+
+    /// Toggles a setting, or switches to the next action for the maximum action
+    fn toggle(&mut self, setting: Setting, chat_id: ChatId, config: &Config) {
+        match setting {
+            Setting::Llm => self.llm = Some(!self.llm_enabled()),
+            Setting::ShadowMode => self.shadow_mode = !self.shadow_mode,
+            Setting::Notices => self.notices = Some(!self.notices_enabled(chat_id, config)),
+            Setting::ServiceMessages => {
+                let service_messages = self.service_messages(chat_id, config);
+                self.service_messages = Some(!service_messages.any());
+            }
+            Setting::MaxAction => {
+                self.max_action = Some(match self.limit_action(Action::Ban) {
+                    Action::None | Action::Ban => Action::Delete,
+                    Action::Delete => Action::Mute,
+                    Action::Mute => Action::Ban,
+                })
+            }
+        }
+    }
+
+    /// Builds the settings menu showing the current value of every setting
+    pub fn keyboard(&self, chat_id: ChatId, config: &Config) -> InlineKeyboardMarkup {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        let rows = Setting::ALL.into_iter().map(|setting| {
+            let label = match setting {
+                Setting::Llm => format!("LLM check: {}", on_off(self.llm_enabled())),
+                Setting::ShadowMode => format!("Shadow mode: {}", on_off(self.shadow_mode)),
+                Setting::Notices => {
+                    format!("Notices: {}", on_off(self.notices_enabled(chat_id, config)))
+                }
+                Setting::ServiceMessages => format!(
+                    "Delete service messages: {}",
+                    on_off(self.service_messages(chat_id, config).any())
+                ),
+                Setting::MaxAction => {
+                    format!("Most severe action: {}", self.limit_action(Action::Ban))
+                }
+            };
+            let data = format!("{}{}", CALLBACK_PREFIX, setting.key());
+            vec![InlineKeyboardButton::callback(label, data)]
+        });
+        InlineKeyboardMarkup::new(rows)
+    }
+}
+
+/// Settings of all chats whose admins changed them
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ChatSettingsStore {
+    chats: Mutex<HashMap<i64, ChatSettings>>,
+}
+
+impl ChatSettingsStore {
+    pub fn get(&self, chat_id: ChatId) -> ChatSettings {
+        self.chats
+            .lock()
+            .unwrap()
+            .get(&chat_id.0)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Toggles a setting of a chat and returns the new settings
+    pub fn toggle(&self, chat_id: ChatId, setting: Setting, config: &Config) -> ChatSettings {
+        let mut chats = self.chats.lock().unwrap();
+        let settings = chats.entry(chat_id.0).or_default();
+        settings.toggle(setting, chat_id, config);
+        *settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::ChatId;
+
+    use crate::{
+        actions::Action,
+        config::Config,
+        settings::{ChatSettingsStore, Setting},
+    };
+
+    #[test]
+    fn test_settings() {
+        let config = Config::from_yaml_str("service_messages:\n  joins: true\n");
+
+        let chat_id = ChatId(-100);
+        let store = ChatSettingsStore::default();
+        let settings = store.get(chat_id);
+        assert!(settings.llm_enabled());
+        assert!(settings.notices_enabled(chat_id, &config));
+        assert!(settings.service_messages(chat_id, &config).joins);
+        assert_eq!(settings.limit_action(Action::Ban), Action::Ban);
+
+        // Service message cleanup is turned off entirely, then on for every kind
+        let settings = store.toggle(chat_id, Setting::ServiceMessages, &config);
+        assert!(!settings.service_messages(chat_id, &config).joins);
+        let settings = store.toggle(chat_id, Setting::ServiceMessages, &config);
+        assert!(settings.service_messages(chat_id, &config).pinned);
+
+        // The most severe action cycles from ban to delete and mute
+        let settings = store.toggle(chat_id, Setting::MaxAction, &config);
+        assert_eq!(settings.limit_action(Action::Ban), Action::Delete);
+        let settings = store.toggle(chat_id, Setting::MaxAction, &config);
+        assert_eq!(settings.limit_action(Action::Ban), Action::Mute);
+        assert_eq!(settings.limit_action(Action::Delete), Action::Delete);
+
+        let settings = store.toggle(chat_id, Setting::ShadowMode, &config);
+        assert!(settings.shadow_mode);
+        assert!(!store.get(ChatId(-200)).shadow_mode);
+
+        assert_eq!(
+            Setting::from_callback_data("settings:shadow_mode"),
+            Some(Setting::ShadowMode)
+        );
+        assert_eq!(Setting::from_callback_data("settings:captcha"), None);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Serialize, de::DeserializeOwned};
+use teloxide::types::ChatId;
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+
+use crate::{
+    admins::AdminCache,
+    config::Config,
+    duplicates::FingerprintStore,
+    federation::{BanList, FederatedBan},
+    flood::FloodTracker,
+    health::Health,
+    history::MessageHistory,
+    modlog::{JsonLinesLog, MODERATION_LOG_FILE},
+    reports::ReportTracker,
+    settings::{ChatSettings, ChatSettingsStore, Setting},
+};
+
+const FLOOD_FILE: &str = "flood.json";
+const FINGERPRINTS_FILE: &str = "fingerprints.json";
+const BANS_FILE: &str = "bans.json";
+const HISTORY_FILE: &str = "history.json";
+const SETTINGS_FILE: &str = "settings.json";
+const CANDIDATES_FILE: &str = "candidates.jsonl";
+
+/// How often the state that is not written on every change is saved
+const SAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Runtime state shared between all updates
+///
+/// If a data directory is configured, the state is loaded from it at startup
+/// and written back periodically and when the bot shuts down. The federated
+/// ban list and the chat settings are also written whenever they change, and
+/// the moderation log and the candidate spam corpus are appended to as
+/// actions are taken and messages are reported.
+#[derive(Default)]
+pub struct State {
+    pub flood: FloodTracker,
+    pub fingerprints: FingerprintStore,
+    pub bans: BanList,
+    pub history: MessageHistory,
+    pub health: Health,
+    pub moderation_log: JsonLinesLog,
+    pub admins: AdminCache,
+    pub settings: ChatSettingsStore,
+    pub reports: ReportTracker,
+    /// Reported messages collected for rule authoring
+    pub candidates: JsonLinesLog,
+}
+
+impl State {
+    pub fn load(config: &Config) -> Result<State> {
+        let Some(data_dir) = &config.data_dir
+        else {
+            return Ok(State {
+                admins: AdminCache::new(config.admin_cache_ttl),
+                ..State::default()
+            });
+        };
+
+        fs::create_dir_all(data_dir)
+            .with_context(|| format!("Failed to create data directory '{}'", data_dir.display()))?;
+        info!("Loading state from '{}'", data_dir.display());
+        Ok(State {
+            flood: load_json(data_dir, FLOOD_FILE)?,
+            fingerprints: load_json(data_dir, FINGERPRINTS_FILE)?,
+            bans: load_json(data_dir, BANS_FILE)?,
+            history: load_json(data_dir, HISTORY_FILE)?,
+            settings: load_json(data_dir, SETTINGS_FILE)?,
+            health: Health::new(Some(data_dir.clone())),
+            moderation_log: JsonLinesLog::new(
+                "moderation log",
+                Some(data_dir.join(MODERATION_LOG_FILE)),
+            ),
+            admins: AdminCache::new(config.admin_cache_ttl),
+            reports: ReportTracker::default(),
+            candidates: JsonLinesLog::new(
+                "candidate spam corpus",
+                Some(data_dir.join(CANDIDATES_FILE)),
+            ),
+        })
+    }
+
+    pub fn save(&self, config: &Config) -> Result<()> {
+        let Some(data_dir) = &config.data_dir
+        else {
+            return Ok(());
+        };
+
+        info!("Saving state to '{}'", data_dir.display());
+        self.prune_history(config);
+        self.save_trackers(data_dir)?;
+        save_json(data_dir, BANS_FILE, &self.bans)?;
+        save_json(data_dir, SETTINGS_FILE, &self.settings)
+    }
+
+    /// Forgets users who have not sent a message for longer than the history is needed
+    fn prune_history(&self, config: &Config) {
+        let retention = config.history_retention() as i64;
+        self.history.prune(Utc::now().timestamp() - retention);
+    }
+
+    /// Saves the state that changes with every message, which is not written
+    /// on every change
+    fn save_trackers(&self, data_dir: &Path) -> Result<()> {
+        save_json(data_dir, FLOOD_FILE, &self.flood)?;
+        save_json(data_dir, FINGERPRINTS_FILE, &self.fingerprints)?;
+        save_json(data_dir, HISTORY_FILE, &self.history)
+    }
+
+    /// Adds a user to the federated ban list and saves it, returning false if
+    /// they were already on it
+    pub fn add_federated_ban(
+        &self,
+        user_id: u64,
+        ban: FederatedBan,
+        config: &Config,
+    ) -> Result<bool> {
+        if !self.bans.add(user_id, ban) {
+            return Ok(false);
+        }
+        if let Some(data_dir) = &config.data_dir {
+            save_json(data_dir, BANS_FILE, &self.bans)?;
+        }
+        Ok(true)
+    }
+
+    /// Removes a user from the federated ban list and saves it, returning
+    /// false if they were not on it
+    pub fn remove_federated_ban(&self, user_id: u64, config: &Config) -> Result<bool> {
+        if !self.bans.remove(user_id) {
+            return Ok(false);
+        }
+        if let Some(data_dir) = &config.data_dir {
+            save_json(data_dir, BANS_FILE, &self.bans)?;
+        }
+        Ok(true)
+    }
+
+    /// Toggles a setting of a chat, saves the settings of all chats and
+    /// returns the new settings of the chat
+    pub fn toggle_setting(
+        &self,
+        chat_id: ChatId,
+        setting: Setting,
+        config: &Config,
+    ) -> Result<ChatSettings> {
+        let settings = self.settings.toggle(chat_id, setting, config);
+        if let Some(data_dir) = &config.data_dir {
+            save_json(data_dir, SETTINGS_FILE, &self.settings)?;
+        }
+        Ok(settings)
+    }
+}
+
+/// Prunes the message history and saves the flood, fingerprint and history
+/// state periodically, so that little is lost if the bot is killed without a
+/// chance to save it
+pub async fn run_autosave(state: &State, config: &Config) {
+    let mut interval = time::interval(SAVE_INTERVAL);
+    // The first tick completes immediately, right after the state was loaded
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        state.prune_history(config);
+        if let Some(data_dir) = &config.data_dir
+            && let Err(error) = state.save_trackers(data_dir)
+        {
+            error!("Failed to save state: {:#}", error);
+        }
+    }
+}
+
+/// Reads a JSON file from the data directory, falling back to the default if it does not exist
+pub fn load_json<T: DeserializeOwned + Default>(data_dir: &Path, name: &str) -> Result<T> {
+    let path = data_dir.join(name);
+    if !path.exists() {
+        return Ok(T::default());
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read state file '{}'", path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse state file '{}'", path.display()))
+}
+
+/// Writes a JSON file to the data directory, replacing it atomically
+pub fn save_json<T: Serialize>(data_dir: &Path, name: &str, value: &T) -> Result<()> {
+    let path = data_dir.join(name);
+    let temporary_path = data_dir.join(format!("{}.tmp", name));
+    fs::write(&temporary_path, serde_json::to_string(value)?)
+        .with_context(|| format!("Failed to write state file '{}'", path.display()))?;
+    fs::rename(&temporary_path, &path)
+        .with_context(|| format!("Failed to replace state file '{}'", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::config::Config;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory in the system temp directory, removed with its contents when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory that is unique to the test process and call
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "aufseher-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir {
+            path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a file relative to the directory, creating parent directories
+    pub fn write(&self, file: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+impl Config {
+    /// Loads a config file with the given contents and no included files
+    pub fn from_yaml_str(yaml: &str) -> Config {
+        let dir = TempDir::new("config");
+        Config::new(String::new(), None, dir.write("aufseher.yaml", yaml)).unwrap()
+    }
+}